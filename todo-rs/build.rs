@@ -0,0 +1,33 @@
+use std::process::Command; // runs git to find the commit being built
+
+/*
+Build script - Cargo runs this before compiling the crate.
+It bakes the git commit and the build time into the binary as compile-time environment variables,
+which GET /version reads with env!() so a deployment can be matched to the exact build it runs.
+*/
+fn main() {
+    // the short sha of the checked out commit, or "unknown" when building outside a git checkout (e.g. from a source tarball)
+    let git_sha = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+
+    // when the build ran, as an RFC 3339 timestamp in UTC
+    let build_time = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BUILD_TIME={}", build_time);
+
+    // rerun when HEAD moves (checkout or commit) so the sha stays current, instead of on every source change
+    println!("cargo:rerun-if-changed=build.rs");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/refs", git_dir);
+    }
+}
+
+// run a git command and return its trimmed output, or None if git is missing or the command failed
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE todos ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+-- number the existing todos 0, 1, 2, ... in creation order
+UPDATE todos SET position = numbered.position
+FROM (SELECT id, (ROW_NUMBER() OVER (ORDER BY id) - 1)::INTEGER AS position FROM todos) AS numbered
+WHERE todos.id = numbered.id;
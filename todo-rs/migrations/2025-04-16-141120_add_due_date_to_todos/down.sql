@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX todos_completed_due_date_idx;
+ALTER TABLE todos DROP COLUMN due_date;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE todos ADD COLUMN due_date TIMESTAMPTZ;
+
+-- covers every column the stats query reads, so it can be answered from the index alone
+CREATE INDEX todos_completed_due_date_idx ON todos (completed, due_date);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX todos_search_vector_idx;
+ALTER TABLE todos DROP COLUMN search_vector;
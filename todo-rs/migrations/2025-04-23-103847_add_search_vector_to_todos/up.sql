@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- kept up to date by Postgres itself whenever the title or content changes, the title weighted above the content
+ALTER TABLE todos ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', content), 'B')
+) STORED;
+
+CREATE INDEX todos_search_vector_idx ON todos USING GIN (search_vector);
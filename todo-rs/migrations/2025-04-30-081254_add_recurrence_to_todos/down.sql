@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE todos DROP COLUMN recurred_from;
+ALTER TABLE todos DROP COLUMN recurrence;
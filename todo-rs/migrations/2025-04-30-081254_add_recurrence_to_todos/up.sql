@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE todos ADD COLUMN recurrence TEXT NOT NULL DEFAULT 'none'
+    CHECK (recurrence IN ('none', 'daily', 'weekly', 'monthly'));
+
+-- the todo a recurring instance was spawned from; UNIQUE so each todo spawns at most one next instance,
+-- even if the scheduler runs twice for it (e.g. right after a restart, or two servers racing)
+ALTER TABLE todos ADD COLUMN recurred_from INT4 UNIQUE REFERENCES todos (id) ON DELETE SET NULL;
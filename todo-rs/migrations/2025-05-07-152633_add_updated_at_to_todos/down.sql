@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER todos_record_deletion ON todos;
+DROP FUNCTION todos_record_deletion();
+DROP TABLE todo_deletions;
+DROP TRIGGER todos_set_updated_at ON todos;
+DROP FUNCTION todos_set_updated_at();
+ALTER TABLE todos DROP COLUMN updated_at;
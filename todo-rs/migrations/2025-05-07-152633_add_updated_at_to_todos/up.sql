@@ -0,0 +1,33 @@
+-- Your SQL goes here
+ALTER TABLE todos ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE todos SET updated_at = created_at;
+
+-- every UPDATE of a todo (including its position shifting when another todo moves) bumps its updated_at,
+-- so the application can't forget to
+CREATE FUNCTION todos_set_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at := NOW();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_set_updated_at BEFORE UPDATE ON todos
+    FOR EACH ROW EXECUTE FUNCTION todos_set_updated_at();
+
+-- a deleted todo leaves no updated_at behind, so the time of the last delete is kept in this one-row table instead
+-- (the list's Last-Modified is the later of the two)
+CREATE TABLE todo_deletions (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id), -- only ever one row
+    last_deleted_at TIMESTAMPTZ
+);
+INSERT INTO todo_deletions DEFAULT VALUES;
+
+CREATE FUNCTION todos_record_deletion() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE todo_deletions SET last_deleted_at = NOW();
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_record_deletion AFTER DELETE ON todos
+    FOR EACH STATEMENT EXECUTE FUNCTION todos_record_deletion();
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE attachments (
+    id SERIAL PRIMARY KEY,
+    todo_id INT4 NOT NULL REFERENCES todos (id) ON DELETE CASCADE, -- deleting a todo deletes its attachments
+    url TEXT NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX attachments_todo_id_idx ON attachments (todo_id);
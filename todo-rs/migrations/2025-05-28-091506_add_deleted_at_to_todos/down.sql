@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER todos_record_soft_deletion ON todos;
+DROP INDEX todos_updated_at_idx;
+DELETE FROM todos WHERE deleted_at IS NOT NULL;
+ALTER TABLE todos DROP COLUMN deleted_at;
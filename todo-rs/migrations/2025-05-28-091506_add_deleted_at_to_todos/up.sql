@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- deleting a todo only sets deleted_at (a soft delete), so GET /todos/changes can tell sync clients which todos went away
+ALTER TABLE todos ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- GET /todos/changes reads the rows changed since a point in time, in updated_at order
+CREATE INDEX todos_updated_at_idx ON todos (updated_at, id);
+
+-- a soft delete is an UPDATE, so it needs its own trigger to record when the list last changed
+CREATE TRIGGER todos_record_soft_deletion AFTER UPDATE OF deleted_at ON todos
+    FOR EACH STATEMENT EXECUTE FUNCTION todos_record_deletion();
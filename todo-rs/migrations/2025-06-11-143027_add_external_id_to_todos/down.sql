@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX todos_external_id_idx;
+ALTER TABLE todos DROP COLUMN external_id;
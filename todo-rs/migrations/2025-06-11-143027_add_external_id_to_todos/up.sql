@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- the id a todo has in the external system it's synced from, see upsert_todo; NULL for todos created here
+ALTER TABLE todos ADD COLUMN external_id TEXT;
+
+-- one live todo per external id, which is also what PUT /todos/external/{external_id} upserts on
+-- (a deleted todo keeps its external id until it's purged, without blocking a new todo from taking it)
+CREATE UNIQUE INDEX todos_external_id_idx ON todos (external_id) WHERE deleted_at IS NULL;
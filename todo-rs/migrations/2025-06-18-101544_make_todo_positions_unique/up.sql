@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- close any gaps and duplicates concurrent writes may already have left behind, the constraint below needs a clean start
+UPDATE todos SET position = numbered.position
+FROM (SELECT id, (ROW_NUMBER() OVER (ORDER BY position, id) - 1)::INTEGER AS position FROM todos WHERE deleted_at IS NULL) AS numbered
+WHERE todos.id = numbered.id AND todos.position <> numbered.position;
+
+-- no two live todos at the same position, so two moves racing each other fail (and are retried) instead of leaving
+-- duplicates behind; an exclusion constraint rather than a unique index because it can be deferred: shifting the todos
+-- between a move's old and new position briefly puts two of them at the same position, only the end result has to be unique
+ALTER TABLE todos ADD CONSTRAINT todos_position_unique EXCLUDE USING btree (position WITH =) WHERE (deleted_at IS NULL)
+    DEFERRABLE INITIALLY DEFERRED;
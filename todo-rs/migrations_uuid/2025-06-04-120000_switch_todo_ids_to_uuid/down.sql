@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+-- the old integer ids are gone, there's nothing to go back to
+DO $$ BEGIN RAISE EXCEPTION 'todo ids can''t be switched back from UUIDs to integers'; END $$;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+
+-- switches todo ids from serial integers to random UUIDs, for builds with the uuid-ids feature (see Cargo.toml)
+-- every todo gets a new id, so anything holding on to the old ones (bookmarks, a sync client's copy) has to start over
+ALTER TABLE todos ADD COLUMN new_id UUID NOT NULL DEFAULT gen_random_uuid();
+ALTER TABLE todos ADD COLUMN new_recurred_from UUID;
+UPDATE todos SET new_recurred_from = previous.new_id FROM todos AS previous WHERE previous.id = todos.recurred_from;
+ALTER TABLE attachments ADD COLUMN new_todo_id UUID;
+UPDATE attachments SET new_todo_id = todos.new_id FROM todos WHERE todos.id = attachments.todo_id;
+
+-- dropping the old columns takes the primary key, the foreign keys and the indexes built on them along
+ALTER TABLE attachments DROP COLUMN todo_id;
+ALTER TABLE todos DROP COLUMN recurred_from;
+ALTER TABLE todos DROP COLUMN id CASCADE;
+
+ALTER TABLE todos RENAME COLUMN new_id TO id;
+ALTER TABLE todos ADD PRIMARY KEY (id);
+ALTER TABLE todos RENAME COLUMN new_recurred_from TO recurred_from;
+ALTER TABLE todos ADD UNIQUE (recurred_from), ADD FOREIGN KEY (recurred_from) REFERENCES todos (id) ON DELETE SET NULL;
+ALTER TABLE attachments RENAME COLUMN new_todo_id TO todo_id;
+ALTER TABLE attachments ALTER COLUMN todo_id SET NOT NULL, ADD FOREIGN KEY (todo_id) REFERENCES todos (id) ON DELETE CASCADE;
+
+CREATE INDEX attachments_todo_id_idx ON attachments (todo_id);
+CREATE INDEX todos_updated_at_idx ON todos (updated_at, id);
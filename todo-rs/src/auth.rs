@@ -0,0 +1,80 @@
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use crate::state::AppState;
+
+/*
+Who may use the /admin endpoints. Every one of them is wired up behind require_admin (see the admin routes in lib.rs),
+so a route's place in the router is what tells you it needs elevated privileges, not something inside its handler.
+An admin is a caller sending Authorization: Bearer <token> with either
+      - ADMIN_TOKEN itself, the shared secret for scripts and operators
+      - a JWT signed (HS256) with JWT_SECRET whose role claim is "admin", e.g. {"sub": "alice", "role": "admin", "exp": 1767225600}
+and anyone else is turned away before the handler runs:
+      - 404 when neither ADMIN_TOKEN nor JWT_SECRET is set, the admin endpoints are disabled as if they didn't exist
+      - 401 without a bearer token, or with one that's neither the admin token nor a valid, unexpired JWT
+      - 403 with a valid JWT for any other role, we know who's calling but they aren't allowed
+*/
+pub struct RequireAdmin;
+
+impl FromRequestParts<AppState> for RequireAdmin {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<RequireAdmin, StatusCode> {
+        let config = &state.config;
+        if config.admin_token.is_none() && config.jwt_secret.is_none() {
+            return Err(StatusCode::NOT_FOUND);
+        }
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        if config.admin_token.as_deref().is_some_and(|admin_token| same_secret(token, admin_token)) {
+            return Ok(RequireAdmin);
+        }
+
+        let secret = config.jwt_secret.as_deref().ok_or(StatusCode::UNAUTHORIZED)?;
+        let claims = jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::new(Algorithm::HS256))
+            .map_err(|_| StatusCode::UNAUTHORIZED)? // a bad signature, an expired token, malformed claims, ...
+            .claims;
+        match claims.role {
+            Role::Admin => Ok(RequireAdmin),
+            Role::User => Err(StatusCode::FORBIDDEN),
+        }
+    }
+}
+
+// RequireAdmin as a middleware, for putting a whole group of routes behind it
+pub async fn require_admin(_: RequireAdmin, request: Request, next: Next) -> Response {
+    next.run(request).await
+}
+
+// Deserialize - the claims of a JWT we care about, the signature and "exp" are checked by jsonwebtoken itself
+#[derive(Deserialize)]
+pub struct Claims {
+    pub sub: String, // who the token was issued to
+    #[serde(default)]
+    pub role: Role, // what they may do, a token without a role is a regular user's
+}
+
+// a caller's role, "admin" or anything else (a regular "user")
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    #[default]
+    #[serde(other)]
+    User,
+}
+
+// compare every byte rather than stopping at the first difference, so the time taken doesn't hint at how much was right
+fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,76 @@
+use std::num::NonZeroUsize; // the capacity of the cache
+use std::sync::Mutex; // the cache is shared by every handler, so access to it is serialized
+use std::time::{Duration, Instant}; // used to expire entries after the TTL
+
+use lru::LruCache; // a map that evicts the least recently used entry once it's full
+use crate::models::{Todo, TodoId};
+
+/*
+TodoCache - an in-memory read-through cache for get_todo, keyed by tenant (see tenant.rs, None without multi-tenancy) and todo id.
+Entries expire after a TTL and the least recently used ones are evicted once the cache is full.
+
+Writes must never leave a stale todo behind, so every write invalidates the entries it affects.
+There is one more race to handle: a get_todo can read a row from the database, then an update commits and
+invalidates the cache, then the get_todo caches the old row it read. To prevent that, every invalidation bumps
+a generation counter; readers note the generation before querying and their insert is dropped if it changed since.
+*/
+pub struct TodoCache {
+    inner: Mutex<Inner>,
+    ttl: Duration, // how long an entry is served before it's re-read from the database
+}
+
+struct Inner {
+    entries: LruCache<(Option<String>, TodoId), (Instant, Todo)>, // (tenant, todo id) -> (when it was cached, the todo)
+    generation: u64, // bumped on every invalidation
+}
+
+impl TodoCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> TodoCache {
+        TodoCache {
+            inner: Mutex::new(Inner { entries: LruCache::new(capacity), generation: 0 }),
+            ttl,
+        }
+    }
+
+    // the cached todo, if there is one that hasn't expired yet
+    pub fn get(&self, tenant: Option<&str>, todo_id: TodoId) -> Option<Todo> {
+        let key = (tenant.map(str::to_string), todo_id);
+        let mut inner = self.inner.lock().unwrap();
+        match inner.entries.get(&key) {
+            Some((cached_at, todo)) if cached_at.elapsed() < self.ttl => Some(todo.clone()),
+            Some(_) => {
+                inner.entries.pop(&key); // expired
+                None
+            }
+            None => None,
+        }
+    }
+
+    // call before reading a todo from the database, and pass the result to insert()
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    // cache a todo read from the database, unless something was invalidated since `generation` was taken
+    pub fn insert(&self, tenant: Option<&str>, todo: Todo, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner.entries.put((tenant.map(str::to_string), todo.id), (Instant::now(), todo));
+        }
+    }
+
+    // forget one todo, after it was updated
+    pub fn invalidate(&self, tenant: Option<&str>, todo_id: TodoId) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.pop(&(tenant.map(str::to_string), todo_id));
+        inner.generation += 1;
+    }
+
+    // forget every todo, after a write that touched many rows (e.g. shifting positions)
+    // (every tenant's, which is more than needed but never wrong)
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.generation += 1;
+    }
+}
@@ -0,0 +1,56 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade}; // axum's WebSocket support
+use axum::extract::State;
+use axum::response::Response;
+use serde::Serialize; // events are sent to clients as JSON
+use tokio::sync::broadcast; // one sender, many subscribers
+use crate::models::{Todo, TodoId};
+use crate::state::AppState;
+use crate::tenant;
+
+// how many events a slow client can fall behind by before it starts missing some
+pub const EVENT_CHANNEL_CAPACITY: usize = 100;
+
+// Serialize - sent over the WebSocket as e.g. {"type":"created","todo":{...}} or {"type":"deleted","id":3}
+// Clone - every subscriber gets its own copy
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TodoEvent {
+    Created { todo: Todo }, // a todo was created (or duplicated)
+    Updated { todo: Todo }, // a todo was edited or moved, carries its new state
+    Deleted { id: TodoId }, // a todo was deleted
+}
+
+// GET /ws
+// Upgrades the connection to a WebSocket that receives every todo change as it happens (only the tenant's own, see tenant.rs)
+pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    let events = state.events.subscribe(); // subscribe before the upgrade so no event in between is missed
+    let tenant = tenant::current(); // the socket outlives the request, so note whose events it gets now
+    ws.on_upgrade(move |socket| push_events(socket, events, tenant))
+}
+
+/*
+Forwards events to one client until it goes away.
+We also read from the socket so that we notice when the client disconnects (or sends a close frame);
+returning drops the receiver, which unsubscribes it from the channel, so disconnected clients don't pile up.
+*/
+async fn push_events(mut socket: WebSocket, mut events: broadcast::Receiver<(Option<String>, TodoEvent)>, tenant: Option<String>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok((event_tenant, _)) if event_tenant != tenant => continue, // another tenant's change
+                Ok((_, event)) => {
+                    let json = serde_json::to_string(&event).unwrap(); // serializing an event can't fail
+                    if socket.send(Message::Text(json.into())).await.is_err() {
+                        return; // the client is gone
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue, // the client was too slow and missed some events, carry on from the newest
+                Err(broadcast::error::RecvError::Closed) => return, // the server is shutting down
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return, // the client disconnected
+                Some(Ok(_)) => {} // we don't expect anything from clients, ignore it
+            },
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::future::Future;
+use axum::body::Bytes;
+use axum::extract::path::ErrorKind;
+use axum::extract::rejection::PathRejection;
+use axum::extract::{FromRequest, FromRequestParts, Path, RawPathParams, Request};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+
+/*
+Json - a drop-in replacement for axum's Json, used by every handler that takes a JSON body (and, for symmetry, the ones returning one).
+axum's own extractor rejects a bad body with a plain-text message. This one answers with 422 Unprocessable Entity and a
+JSON body that says what's wrong and where, e.g. for {"title": "Milk", "content": 5}:
+      {"error": "invalid JSON body", "field": "content", "detail": "invalid type: integer `5`, expected a string", "line": 1, "column": 29}
+"field" is the path to the problem (e.g. "todos[2].title"), null when it's the body as a whole: a missing field is reported
+on the object it's missing from, with the field's name in the detail ("missing field `title`"), and so is malformed JSON.
+A body that isn't sent as application/json gets 415 Unsupported Media Type, like with axum's extractor.
+*/
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Json<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Json<T>, Response> {
+        if !is_json(request.headers()) {
+            return Err(unsupported_media_type());
+        }
+
+        let bytes = Bytes::from_request(request, state).await.map_err(IntoResponse::into_response)?; // e.g. 413 for a body that's too big
+
+        // serde_path_to_error keeps track of where in the document the deserializer was when it failed
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        let value = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|e| {
+                let path = e.path().to_string();
+                let field = (path != ".").then_some(path); // "." is the document itself
+                json_error(field, e.inner())
+            })?;
+        deserializer.end().map_err(|e| json_error(None, &e))?; // nothing but whitespace may follow the value
+
+        Ok(Json(value))
+    }
+}
+
+// compact JSON like axum's, or indented while handling a request that asked for it (see pretty_json in middleware.rs)
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        if !PRETTY.try_with(|pretty| *pretty).unwrap_or(false) {
+            return axum::Json(self.0).into_response();
+        }
+
+        match serde_json::to_vec_pretty(&self.0) {
+            Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(), // what axum::Json does too
+        }
+    }
+}
+
+tokio::task_local! {
+    // whether the request being handled asked for indented JSON
+    static PRETTY: bool;
+}
+
+// run `future` (the rest of a request) with every Json it responds with indented or not
+pub async fn pretty_scope<F: Future>(pretty: bool, future: F) -> F::Output {
+    PRETTY.scope(pretty, future).await
+}
+
+// the request's Content-Type without its parameters (e.g. ; charset=utf-8 or ; boundary=...), lowercased
+fn media_type(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    Some(content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+}
+
+// whether the request says its body is JSON: application/json, or a JSON-based type like application/merge-patch+json
+pub(crate) fn is_json(headers: &HeaderMap) -> bool {
+    media_type(headers).is_some_and(|mime| mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json")))
+}
+
+// whether the request says its body is a file upload, which the multipart routes (POST /todos/import-file) take instead of JSON
+pub(crate) fn is_multipart(headers: &HeaderMap) -> bool {
+    media_type(headers).is_some_and(|mime| mime == "multipart/form-data")
+}
+
+// 415 for a body that isn't sent as JSON, also used by the require_json middleware
+pub(crate) fn unsupported_media_type() -> Response {
+    invalid_body(StatusCode::UNSUPPORTED_MEDIA_TYPE, None, "expected a Content-Type of application/json", None)
+}
+
+// 422 for a body serde couldn't read, split into what went wrong and where
+fn json_error(field: Option<String>, error: &serde_json::Error) -> Response {
+    // serde_json's message ends with the position, which the response has separate fields for
+    let message = error.to_string();
+    let location = format!(" at line {} column {}", error.line(), error.column());
+    let detail = message.strip_suffix(&location).unwrap_or(&message);
+
+    invalid_body(StatusCode::UNPROCESSABLE_ENTITY, field, detail, Some((error.line(), error.column())))
+}
+
+fn invalid_body(status: StatusCode, field: Option<String>, detail: &str, position: Option<(usize, usize)>) -> Response {
+    let mut body = json!({ "error": "invalid JSON body", "field": field, "detail": detail });
+    if let Some((line, column)) = position {
+        body["line"] = json!(line);
+        body["column"] = json!(column);
+    }
+    (status, Json(body)).into_response()
+}
+
+/*
+IdPath - axum's Path for the ids in a URL, e.g. /todos/{id} or /todos/{id}/attachments/{attachment_id}, that also checks
+they're in range. axum's own extractor rejects an id that doesn't parse with a plain-text 400, and happily lets through
+ids like 0 or -3 that can never match a row. This one answers both with 400 Bad Request and a JSON body naming the id:
+      {"error": "invalid path parameter", "field": "id", "detail": "id must be a positive integer"}
+What counts as a valid id is up to its type (see PathId below): integers have to be greater than 0, and any UUID
+(with the uuid-ids feature) is fine once it parses.
+*/
+pub struct IdPath<T>(pub T);
+
+impl<T: PathIds, S: Send + Sync> FromRequestParts<S> for IdPath<T> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<IdPath<T>, Response> {
+        // the names of the path's parameters, in order, so an error can say which one it's about
+        let keys: Vec<String> = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .collect();
+        let invalid = |index: usize| invalid_id(keys.get(index).map_or("id", String::as_str), T::expected(index));
+
+        let ids = match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(ids)) => ids,
+            Err(PathRejection::FailedToDeserializePathParams(e)) => {
+                let index = match e.kind() {
+                    ErrorKind::ParseErrorAtIndex { index, .. } => *index,
+                    ErrorKind::ParseErrorAtKey { key, .. } | ErrorKind::DeserializeError { key, .. } => {
+                        keys.iter().position(|name| name == key).unwrap_or(0)
+                    }
+                    _ => 0, // e.g. a single id that didn't parse, which has no key or index to go by
+                };
+                return Err(invalid(index));
+            }
+            Err(rejection) => return Err(rejection.into_response()), // a route set up wrong, 500
+        };
+
+        match ids.out_of_range() {
+            Some(index) => Err(invalid(index)),
+            None => Ok(IdPath(ids)),
+        }
+    }
+}
+
+// a type an id in a path can have, and which of its values are valid ids
+pub trait PathId: DeserializeOwned + Send {
+    // what a valid id looks like, for the error message, e.g. "a positive integer"
+    const EXPECTED: &'static str;
+
+    fn in_range(&self) -> bool {
+        true
+    }
+}
+
+impl PathId for i32 {
+    const EXPECTED: &'static str = "a positive integer";
+
+    fn in_range(&self) -> bool {
+        *self > 0 // serial ids start at 1
+    }
+}
+
+#[cfg(feature = "uuid-ids")]
+impl PathId for uuid::Uuid {
+    const EXPECTED: &'static str = "a UUID";
+}
+
+// the ids a whole path holds: one on its own, or a pair like (todo id, attachment id)
+pub trait PathIds: DeserializeOwned + Send {
+    // what the id at `index` should look like
+    fn expected(index: usize) -> &'static str;
+    // the index of the first id that isn't valid, if any
+    fn out_of_range(&self) -> Option<usize>;
+}
+
+impl<T: PathId> PathIds for T {
+    fn expected(_index: usize) -> &'static str {
+        T::EXPECTED
+    }
+
+    fn out_of_range(&self) -> Option<usize> {
+        (!self.in_range()).then_some(0)
+    }
+}
+
+impl<A: PathId, B: PathId> PathIds for (A, B) {
+    fn expected(index: usize) -> &'static str {
+        if index == 0 { A::EXPECTED } else { B::EXPECTED }
+    }
+
+    fn out_of_range(&self) -> Option<usize> {
+        if !self.0.in_range() {
+            Some(0)
+        } else {
+            (!self.1.in_range()).then_some(1)
+        }
+    }
+}
+
+// 400 for an id in the path that doesn't parse or can't be one
+fn invalid_id(key: &str, expected: &str) -> Response {
+    let body = json!({ "error": "invalid path parameter", "field": key, "detail": format!("{} must be {}", key, expected) });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
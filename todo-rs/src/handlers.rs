@@ -1,103 +1,1764 @@
+use std::hash::{DefaultHasher, Hash, Hasher}; // the export's ETag is a hash of its todos
 use std::sync::Arc; // Arc is used to share ownership of the db connection pool across multiple handlers safely
 
 use axum::{
+    body::Body, // the response body type, used to stream NDJSON
     extract::State, // extracts global state (like the DB connection pool)
-    http::StatusCode, // used for HTTP status codes
-    Json, // handles JSON serialization or deserialization
+    http::{header, HeaderMap, HeaderValue, StatusCode}, // used for HTTP headers and status codes
+    response::{IntoResponse, Response}, // lets a handler return different response shapes
 };
-use axum::extract::Path; // extracts the path parameters from the request
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc}; // Last-Modified times, compared to whole seconds
+use futures::stream; // builds the async stream of NDJSON lines
+use axum::extract::{Multipart, Path, Query}; // extracts the path parameters, query string and multipart uploads from the request
+use diesel::dsl::sql; // raw SQL fragments, for the aggregate FILTER clauses Diesel has no builder for
+use diesel::pg::Pg; // the PostgreSQL backend, needed to name boxed query types
+use diesel::sql_types::{BigInt, Bool, Integer, Timestamptz}; // the SQL types of COUNT(*), xmax = 0, an advisory lock key and NOW()
 use diesel::prelude::*; // imports Diesel's query builder and ORM functionality
+use diesel::connection::SimpleConnection; // batch_execute, for the SET statements in run_db
+use diesel::upsert::excluded; // the row an INSERT ... ON CONFLICT DO UPDATE tried to insert
+use diesel::result::DatabaseErrorKind; // what kind of error the database reported, see db_error
 use diesel::r2d2; // Diesel's connection pooling
 use diesel::r2d2::ConnectionManager; // Manages database connections in the pool
-use crate::models::{NewTodo, Todo, UpdateTodo}; // importing the models
-use crate::schema::todos; // importing todos table
+use serde_json::{json, Value}; // builds ad-hoc JSON bodies that don't need their own struct
+use crate::events::TodoEvent; // change events pushed to WebSocket clients
+use crate::extract::{IdPath, Json}; // the ids in the path and JSON bodies, both with detailed errors when they're bad
+use crate::models::{ // importing the models
+    Attachment, BoardQuery, ChangesQuery, ClearConfirm, DryRun, ImportDocument, ImportMode, ImportParams, ImportTodo, ListFormat, ListPage,
+    MoveTodo, NewAttachment, NewTodo, Partial, ReadOnlyToggle, SearchQuery, SearchResult, SortOrder, Todo, TodoFilter, TodoId, TodoIds,
+    TodoInclude, TodoSort, UpdateTodo, TODO_FIELDS,
+};
+use crate::schema::{attachments, todo_deletions, todos}; // importing the tables
+use crate::search::{english, search_vector, to_tsquery, ts_headline, ts_rank, Matches}; // Postgres full-text search
+use crate::state::AppState; // the shared state every handler receives
+use crate::range::ranged_response; // answers Range requests for the export
+use crate::retry::{is_transient, with_retry}; // runs the writes again after a transient database error
+use crate::tenant; // which tenant's schema a request's queries run in
 use crate::schema::todos::id; // importing the id column from the todos table
 
 // define DbPool as a shared reference (Arc) to a db connection pool
 // use r2d2::Pool to manage PostgreSQL connections
 pub type DbPool = Arc<r2d2::Pool<ConnectionManager<PgConnection>>>;
 
+/*
+Diesel and r2d2 are synchronous: checking a connection out of the pool and running a query both block the calling thread.
+Doing that directly in an async handler would stall one of Tokio's worker threads, and every other request scheduled on it,
+for as long as the query (or the wait for a free connection) takes. So every database call goes through run_db, which runs
+the closure with a pooled connection on Tokio's blocking thread pool and awaits the result without blocking anything.
+A panic inside the closure is re-raised in the handler, so CatchPanicLayer still turns it into a 500.
+
+Under heavy load, queueing every request for a connection just makes them all slow and then time out together.
+So when the pool is saturated (every connection exists and is in use), a request only waits POOL_SHED_AFTER_MS for
+one to free up, instead of the pool's full connection timeout, and is then shed: 503 with a Retry-After header
+(added for every 503 in lib.rs), a warning in the logs and one more on the requests_shed metric.
+
+With MULTI_TENANT=true the connection is also pointed at the current tenant's schema before the closure runs
+and back at the default one afterwards (even if the closure panics), see tenant.rs.
+
+A runaway query is cut off by Postgres itself after DB_STATEMENT_TIMEOUT_MS, rather than only by the request timeout
+(which drops the handler but leaves the query running and the connection taken). The timeout is a session setting
+set on checkout and reset afterwards, also if the closure panics: SET LOCAL would undo itself, but only lasts until
+the end of a transaction, and most queries here don't run in one. A cancelled query answers 504, see db_error.
+*/
+pub(crate) async fn run_db<T, F>(state: &AppState, query: F) -> Result<T, StatusCode>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut PgConnection) -> Result<T, StatusCode> + Send + 'static,
+{
+    let pool = state.pool.clone();
+    let shed_after = state.config.pool_shed_after;
+    let metrics = state.metrics.clone();
+    let multi_tenant = state.config.multi_tenant;
+    let statement_timeout = state.config.statement_timeout;
+    let tenant = tenant::current(); // task-locals don't follow us onto the blocking thread, so read it here
+    let span = tracing::info_span!("db_query"); // a child of the request's span, so a trace shows the time spent in the database
+    let result = tokio::task::spawn_blocking(move || {
+        let pool_state = pool.state();
+        let saturated = pool_state.idle_connections == 0 && pool_state.connections == pool.max_size();
+
+        let mut conn = if saturated {
+            pool.get_timeout(shed_after).map_err(|_| {
+                metrics.record_shed_request();
+                tracing::warn!(in_use = pool_state.connections, "database pool saturated, shedding request");
+                StatusCode::SERVICE_UNAVAILABLE
+            })?
+        } else {
+            pool.get().map_err(|_| StatusCode::SERVICE_UNAVAILABLE)? // 503 if no connection frees up in time
+        };
+        let _entered = span.enter(); // from here on, once there's a connection to query with
+        if !multi_tenant && statement_timeout.is_none() {
+            return query(&mut conn);
+        }
+
+        // set on every checkout rather than trusting the reset below, so a connection can never carry one tenant's
+        // search_path into another tenant's request
+        if multi_tenant {
+            tenant::set_search_path(&mut conn, tenant.as_deref()).map_err(db_error)?;
+        }
+        if let Some(timeout) = statement_timeout {
+            conn.batch_execute(&format!("SET statement_timeout = {}", timeout.as_millis())).map_err(db_error)?; // in milliseconds
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| query(&mut conn)));
+        // both fail only in an aborted transaction, which is rolled back anyway
+        if statement_timeout.is_some() {
+            let _ = conn.batch_execute("RESET statement_timeout");
+        }
+        if multi_tenant {
+            let _ = tenant::set_search_path(&mut conn, None);
+        }
+        result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+    .await;
+
+    match result {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(StatusCode::SERVICE_UNAVAILABLE), // the runtime is shutting down
+    }
+}
+
+// how many rows each NDJSON page loads from the database at a time
+const NDJSON_BATCH_SIZE: i64 = 500;
+
+// how many changes one page of GET /todos/changes holds
+const CHANGES_PAGE_SIZE: i64 = 500;
+
+// how far back from the current time the next ?since= of GET /todos/changes starts, see todo_changes
+const CHANGES_OVERLAP_SECS: i64 = 5;
+
+// map a failed query to a status code: a missing row is 404 Not Found, anything else goes through db_error
+pub(crate) fn query_error(e: diesel::result::Error) -> StatusCode {
+    match e {
+        diesel::result::Error::NotFound => StatusCode::NOT_FOUND,
+        e => db_error(e),
+    }
+}
+
+// map a database error to a status code: a query Postgres cancelled for running past DB_STATEMENT_TIMEOUT_MS is
+// 504 Gateway Timeout, a write that lost a race for a position (see is_position_collision) is 409 Conflict,
+// anything else is a server error
+// diesel has no error kind for a cancelled query (SQLSTATE 57014), so it's recognised by Postgres' message
+pub(crate) fn db_error(e: diesel::result::Error) -> StatusCode {
+    match e {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::Unknown, info)
+            if info.message().contains("canceling statement due to statement timeout") =>
+        {
+            tracing::warn!("query cancelled by statement_timeout");
+            StatusCode::GATEWAY_TIMEOUT
+        }
+        e if is_position_collision(&e) => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// the constraint that keeps two live todos from sharing a position, see its migration
+const POSITION_CONSTRAINT: &str = "todos_position_unique";
+
+// whether a write failed because a concurrent one took the same position first, e.g. two tabs reordering at once
+// the constraint is only checked at commit, with both writes' lists already shifted, so the loser can't tell what it
+// collided with: with_retry runs the whole write again, reading the positions afresh (see is_transient)
+pub fn is_position_collision(e: &diesel::result::Error) -> bool {
+    matches!(e, diesel::result::Error::DatabaseError(_, info) if info.constraint_name() == Some(POSITION_CONSTRAINT))
+}
+
+// the ETag of a todo is its version number and its position, e.g. "3-0", so it changes on every update and also when
+// the todo moves, or is shifted by another todo's move or delete, which change its position but aren't edits to it
+fn etag(todo: &Todo) -> String {
+    format!("\"{}-{}\"", todo.version, todo.position)
+}
+
+// whether the If-None-Match header(s) list `etag` (or are "*"), meaning the client's copy is still current
+// this is a weak comparison as HTTP requires for If-None-Match, so W/"3-0" matches "3-0"
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+// read the (version, position) pairs listed in the If-Match header(s), e.g. If-Match: "3-0" or If-Match: "3-0", "4-2"
+// None means there's no precondition: the header is absent or is "*" (matches whatever version exists)
+// tags that aren't one of our ETags are kept out of the list, so they can never match
+fn if_match_tags(headers: &HeaderMap) -> Option<Vec<(i32, i32)>> {
+    let values: Vec<&str> = headers
+        .get_all(header::IF_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    if values.is_empty() || values.contains(&"*") {
+        return None;
+    }
+
+    let parse = |tag: &str| {
+        let (version, position) = tag.strip_prefix('"')?.strip_suffix('"')?.split_once('-')?;
+        Some((version.parse().ok()?, position.parse().ok()?))
+    };
+    Some(values.iter().filter_map(|tag| parse(tag)).collect())
+}
+
+/*
+Deleting a todo is a soft delete: the row stays, with deleted_at set, so GET /todos/changes can tell sync clients it's gone.
+To the rest of the API a deleted todo doesn't exist, so every query that reads or changes todos filters on live().
+The row (and its attachments, which go with it) is removed for good by the purge job once it's old enough, see purge.rs.
+*/
+pub(crate) fn live() -> diesel::dsl::IsNull<todos::deleted_at> {
+    todos::deleted_at.is_null()
+}
+
+/*
+Runs `f` in a transaction, like conn.transaction(f), except that with ?dry_run=true the transaction is always rolled back.
+The writes still really happen inside it, so a dry run selects exactly the rows (and fails exactly where) the real run would,
+and `f` gets to return what it did before it's undone.
+*/
+fn transaction_or_dry_run<T>(
+    conn: &mut PgConnection,
+    dry_run: bool,
+    f: impl FnOnce(&mut PgConnection) -> QueryResult<T>,
+) -> QueryResult<T> {
+    if !dry_run {
+        return conn.transaction(f);
+    }
+
+    let mut outcome = None;
+    let rolled_back = conn.transaction(|conn| {
+        outcome = Some(f(conn)?);
+        Err(diesel::result::Error::RollbackTransaction) // an error makes diesel roll the transaction back
+    });
+    match rolled_back {
+        Err(diesel::result::Error::RollbackTransaction) => outcome.ok_or(diesel::result::Error::RollbackTransaction),
+        other => other, // `f` failed, so there's nothing to report
+    }
+}
+
+/*
+The position a new todo gets so it lands at the end of the list, one past the current last position.
+Every write that adds todos goes through here, so the read of the last position is where two of them at the same time
+would pick the same one and the second would fail on todos_position_unique when it commits. To keep plain creates from
+ever failing like that, this first takes the list's insert lock (see lock_list_for_inserts), so concurrent inserts
+into one list get their positions one after the other. It has to run in the transaction that then inserts the todos.
+*/
+pub(crate) fn next_position(conn: &mut PgConnection) -> QueryResult<i32> {
+    lock_list_for_inserts(conn)?;
+    let last = todos::table.filter(live()).select(diesel::dsl::max(todos::position)).first::<Option<i32>>(conn)?;
+    Ok(last.map_or(0, |last| last + 1)) // an empty list starts at 0
+}
+
+/*
+MAX_TODOS_PER_USER: whether `adding` more todos still fit in the list, checked by every write that inserts todos
+(POST /todos, a duplicate, the first sync of an external todo, the imports and the recurring todo scheduler).
+Todos don't belong to individual users here, so "a user's todos" is the list the request works on: the whole table,
+or with multi-tenancy the tenant's (see tenant.rs).
+It has to run in the transaction that then does the inserting: it first takes the list's insert lock, so two writes at
+the same time can't both count one below the cap and both get in.
+*/
+pub(crate) fn check_todo_limit(conn: &mut PgConnection, max_todos: Option<i64>, adding: usize) -> QueryResult<Result<(), TodoLimitReached>> {
+    let Some(max_todos) = max_todos else {
+        return Ok(Ok(())); // no cap
+    };
+
+    lock_list_for_inserts(conn)?;
+    let count = todos::table.filter(live()).count().get_result::<i64>(conn)?;
+    if count + adding as i64 > max_todos {
+        return Ok(Err(TodoLimitReached(max_todos)));
+    }
+    Ok(Ok(()))
+}
+
+// an advisory lock for the list, held until the transaction ends, taken by every write that adds todos before it counts
+// them (check_todo_limit) or picks their positions (next_position); keyed by the schema the list lives in, so one tenant's
+// writes never wait on another's. Postgres lets a transaction take the same lock again, so taking it in both is fine.
+fn lock_list_for_inserts(conn: &mut PgConnection) -> QueryResult<()> {
+    diesel::select(sql::<Bool>("pg_advisory_xact_lock(").bind::<Integer, _>(LIST_INSERT_LOCK_KEY).sql(", hashtext(current_schema()))"))
+        .execute(conn)
+        .map(|_| ())
+}
+
+// the first half of the advisory lock lock_list_for_inserts takes, the second is the list's schema;
+// like PURGE_LOCK_KEY in purge.rs, an arbitrary number no other lock in the database uses
+const LIST_INSERT_LOCK_KEY: i32 = 0x6c69_6d74; // "limt"
+
+// the answer to a write that would take the list over MAX_TODOS_PER_USER, 409 Conflict with e.g.
+// {"error": "todo limit reached", "detail": "a list can hold at most 500 todos, delete some before adding more"}
+pub(crate) struct TodoLimitReached(pub i64);
+
+impl IntoResponse for TodoLimitReached {
+    fn into_response(self) -> Response {
+        let detail = format!("a list can hold at most {} todos, delete some before adding more", self.0);
+        (StatusCode::CONFLICT, Json(json!({ "error": "todo limit reached", "detail": detail }))).into_response()
+    }
+}
+
+// renumber all todos 0, 1, 2, ... keeping their current order, used after deleting several todos at once leaves gaps
+fn renumber_positions(conn: &mut PgConnection) -> QueryResult<usize> {
+    diesel::sql_query(
+        "UPDATE todos SET position = numbered.position \
+         FROM (SELECT id, (ROW_NUMBER() OVER (ORDER BY position, id) - 1)::INTEGER AS position FROM todos WHERE deleted_at IS NULL) AS numbered \
+         WHERE todos.id = numbered.id AND todos.position <> numbered.position",
+    )
+    .execute(conn)
+}
+
+/*
+Every filter value has to mean something, so a request that got one wrong is told so (400) instead of getting a list that's
+only empty because of the mistake, which would look just like "nothing matches".
+Values that don't parse (?completed=maybe, ?created_after=yesterday) are already rejected by the Query extractor.
+What's left is a window that ends before it starts, or an empty ?ids= (e.g. ?ids= or ?ids=,), which are well-formed but
+can never match a todo, and more ?ids= than MAX_PAGE_SIZE, which is more than one response may hold.
+Only a filter that's valid and matches nothing gives 200 with an empty list.
+*/
+fn check_filter(state: &AppState, filter: &TodoFilter) -> Result<(), StatusCode> {
+    if let (Some(after), Some(before)) = (filter.created_after, filter.created_before) {
+        if after > before {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    match &filter.ids {
+        Some(ids) if ids.is_empty() || ids.len() as i64 > state.config.max_page_size => Err(StatusCode::BAD_REQUEST),
+        _ => Ok(()),
+    }
+}
+
+// build a boxed todos query with the optional filters applied
+// boxing lets us add filters conditionally, and both the list and count endpoints share it so they always agree
+fn filtered_todos(filter: &TodoFilter) -> todos::BoxedQuery<'static, Pg> {
+    let mut query = todos::table.filter(live()).into_boxed();
+
+    // archived todos are left out unless ?archived=true asks for them (and only them),
+    // but todos looked up by ?ids= are returned whichever they are, the caller asked for exactly those
+    if filter.ids.is_none() {
+        query = query.filter(todos::archived.eq(filter.archived));
+    }
+    if let Some(done) = filter.completed {
+        query = query.filter(todos::completed.eq(done)); // only keep todos with the requested completed status
+    }
+    if let Some(after) = filter.created_after {
+        query = query.filter(todos::created_at.ge(after)); // created within the window's start
+    }
+    if let Some(before) = filter.created_before {
+        query = query.filter(todos::created_at.le(before)); // and its end
+    }
+    if let Some(ids) = &filter.ids {
+        query = query.filter(id.eq_any(ids.clone())); // only the listed todos, in a single id = ANY($1)
+    }
+
+    query
+}
+
 // POST
 /*
 In this handler, we accept NewTodo request and will create new record in database. In axum handlers, you can see a state beside request body and they are used for passing dependencies like database connection pools to use for db operations.
+
+With MAX_TODOS_PER_USER set, a list that already holds that many live todos gets 409 Conflict instead, see check_todo_limit.
 */
 pub async fn create_todo(
-    State(db): State<DbPool>, // accept db connection pool as dependency
+    State(state): State<AppState>, // accept the app state (and its db connection pool) as dependency
     Json(new_todo): Json<NewTodo> // request body as NewTodo
-) -> (StatusCode, Json<Todo>) {
-    let mut conn = db
-        .get()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .unwrap(); // get available connection from DB connection pool, throw error otherwise
+) -> Result<(StatusCode, Json<Todo>), Response> {
+    let max_todos = state.config.max_todos_per_user;
+
+    // get available connection from DB connection pool (503 if none frees up in time) and run the queries on it
+    // (again if they hit a transient error, see retry.rs)
+    let todo = with_retry(&state, move |conn| {
+        conn.transaction(|conn| {
+            if let Err(full) = check_todo_limit(conn, max_todos, 1)? {
+                return Ok(Err(full));
+            }
+
+            // new todos are appended to the end of the list
+            let position = next_position(conn)?;
 
-    let todo = diesel 
-        ::insert_into(todos::table) // insert new_todos in todos table
-        .values(&new_todo)
-        .get_result(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .unwrap();
+            diesel 
+                ::insert_into(todos::table) // insert new_todos in todos table
+                .values((&new_todo, todos::position.eq(position)))
+                .get_result::<Todo>(conn)
+                .map(Ok)
+        })
+    })
+    .await
+    .map_err(IntoResponse::into_response)?
+    .map_err(IntoResponse::into_response)?;
 
-    (StatusCode::CREATED, Json(todo)) // return CREATED status code and new todo item as response body
+    state.publish(TodoEvent::Created { todo: todo.clone() }); // notify WebSocket clients
+    Ok((StatusCode::CREATED, Json(todo))) // return CREATED status code and new todo item as response body
 }
 
+// UPSERT by external id
+/*
+For syncing todos from another system that has its own stable ids: PUT /todos/external/{external_id} with the same body as
+POST /todos creates the todo the first time (201 Created) and after that updates its title, content, due date and
+recurrence (200 OK), so syncing the same todo again never duplicates it. The id is stored in the todo's external_id.
+It's a single INSERT ... ON CONFLICT (external_id) DO UPDATE, so two syncs racing each other can't both insert.
+Only live todos count: once a synced todo is deleted here, syncing it again creates a new one.
+Whether the row was inserted comes from Postgres itself, the xmax system column is 0 for a row this statement inserted.
+A sync that would create a todo in a list that's full (MAX_TODOS_PER_USER) gets 409, updating one that's there still works.
+*/
+pub async fn upsert_todo(
+    Path(external_id): Path<String>,
+    State(state): State<AppState>,
+    Json(new_todo): Json<NewTodo>,
+) -> Result<Response, Response> {
+    if external_id.len() > MAX_EXTERNAL_ID_LEN {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
+    }
+    let max_todos = state.config.max_todos_per_user;
+
+    let (todo, inserted) = with_retry(&state, move |conn| {
+        conn.transaction(|conn| {
+            if max_todos.is_some() {
+                // under the list's insert lock, so another sync of this todo can't insert it between this check and ours
+                // (a delete that gets in between only frees up room, so skipping the count for it is still safe)
+                lock_list_for_inserts(conn)?;
+                let synced_before = diesel::select(diesel::dsl::exists(todos::table.filter(live()).filter(todos::external_id.eq(&external_id))))
+                    .get_result::<bool>(conn)?;
+                if !synced_before {
+                    if let Err(full) = check_todo_limit(conn, max_todos, 1)? {
+                        return Ok(Err(full));
+                    }
+                }
+            }
+            let position = next_position(conn)?; // where the todo goes if it's new, an update leaves its position alone
+
+            diesel::insert_into(todos::table)
+                .values((&new_todo, todos::position.eq(position), todos::external_id.eq(&external_id)))
+                .on_conflict(todos::external_id)
+                .filter_target(live()) // matches the partial unique index, see the add_external_id_to_todos migration
+                .do_update()
+                .set((
+                    todos::title.eq(excluded(todos::title)),
+                    todos::content.eq(excluded(todos::content)),
+                    todos::due_date.eq(excluded(todos::due_date)),
+                    todos::recurrence.eq(excluded(todos::recurrence)),
+                    todos::version.eq(todos::version + 1),
+                ))
+                .returning((todos::all_columns, sql::<Bool>("xmax = 0")))
+                .get_result::<(Todo, bool)>(conn)
+                .map(Ok)
+        })
+    })
+    .await
+    .map_err(IntoResponse::into_response)?
+    .map_err(IntoResponse::into_response)?;
+
+    let status = if inserted {
+        state.publish(TodoEvent::Created { todo: todo.clone() });
+        StatusCode::CREATED
+    } else {
+        state.invalidate_todo(todo.id);
+        state.publish(TodoEvent::Updated { todo: todo.clone() });
+        StatusCode::OK
+    };
+    Ok((status, [(header::ETAG, etag(&todo))], Json(todo)).into_response())
+}
+
+// the longest external id upsert_todo accepts, anything longer is most likely not an id at all
+const MAX_EXTERNAL_ID_LEN: usize = 255;
+
 // GET
 /*
 This time, we don't expect to see something in body, we just return todos items by using load function and cast them to Todo struct. As always, return results in response body with status code OK
+An optional ?completed=true|false query param narrows the list down to done or pending todos
+?created_after= and ?created_before= (RFC 3339 timestamps) narrow it down to todos created within a window, and combine with the other filters
+?ids=1,2,3 fetches just those todos in one call, e.g. the ones a page references: ids that don't exist (or were deleted) are
+simply left out, and the rest come in the list's usual order (not the order of ?ids=), archived or not, all in one page
+(up to MAX_PAGE_SIZE ids, more is 400, as is an empty ?ids=)
+Archived todos are left out, ?archived=true lists them instead
+The list comes in pages: ?limit= todos (DEFAULT_PAGE_SIZE when absent, at most MAX_PAGE_SIZE) after skipping ?offset= of them
+It's in the user's own order unless ?sort=created_at|updated_at|due_date|title|version (and ?order=asc|desc) says otherwise, see sorted()
+sort=version counts the edits to a todo (updates, completing, archiving, syncs), moving it around the list doesn't count
+A deployment can pick another default with DEFAULT_SORT and DEFAULT_ORDER, used for whichever of the two a request leaves out
+The JSON list is a bare array, unless ?envelope=true wraps it with the page it is and how many todos there are in all:
+{"data": [...], "page": {"limit": 20, "offset": 40, "total": 135}}
+Passing ?format=ndjson streams every todo instead (in id order, no pages), see stream_todos below
+Sending Accept: text/csv returns the list as a CSV file instead of JSON, which can be downloaded in parts with Range, see todos_csv
+?fields=id,title trims each todo in a JSON or NDJSON response down to just those fields (the CSV columns are fixed)
+
+The response carries a Last-Modified header: when the list last changed, i.e. the latest updated_at of any todo (not only
+the ones the filter matches, see list_last_modified), or the last delete if that came later. A polling client sends it back as If-Modified-Since and gets an empty
+304 Not Modified while nothing has changed, instead of downloading the whole list again.
+HTTP dates only have whole seconds, so the comparison is done on whole seconds as well.
 */
 pub async fn get_todos(
-    State(db): State<DbPool>,
-) -> (StatusCode,Json<Vec<Todo>>) {
-    let mut conn = db.get().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
+    State(state): State<AppState>,
+    Query(filter): Query<TodoFilter>,
+    Query(list_format): Query<ListFormat>,
+    Query(page): Query<ListPage>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    check_filter(&state, &filter)?;
+    let fields = selected_fields(list_format.fields.as_deref())?; // 400 for a field todos don't have
+    // a lookup by ?ids= gets all of its todos in one page unless it asks for a smaller one (there are at most MAX_PAGE_SIZE)
+    let limit = match &filter.ids {
+        Some(ids) => page_limit(&state, Some(page.limit.unwrap_or(ids.len() as i64)))?,
+        None => page_limit(&state, page.limit)?,
+    };
+    if page.offset < 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // the deployment's default for whichever of the two the client left out
+    let sort = page.sort.unwrap_or(state.config.default_sort);
+    let order = page.order.unwrap_or(state.config.default_order);
+
+    match list_format.format.as_deref() {
+        None | Some("json") => {}
+        Some("ndjson") => return Ok(stream_todos(state, filter, fields)),
+        Some(_) => return Err(StatusCode::BAD_REQUEST), // unknown format requested
+    }
+
+    let if_modified_since = if_modified_since(&headers);
+    let (last_modified, results) = run_db(&state, move |conn| {
+        let last_modified = list_last_modified(conn).map_err(db_error)?;
+        if let (Some(last_modified), Some(since)) = (last_modified, if_modified_since) {
+            if last_modified <= since {
+                return Ok((Some(last_modified), None)); // unchanged, no need to load the list
+            }
+        }
+
+        let results = sorted(filtered_todos(&filter), sort, order)
+            .limit(limit)
+            .offset(page.offset)
+            .load::<Todo>(conn)
+            .map_err(db_error)?;
+        // the total is an extra COUNT(*), so it's only run when the envelope asks for it
+        let total = if page.envelope {
+            Some(filtered_todos(&filter).count().get_result::<i64>(conn).map_err(db_error)?)
+        } else {
+            None
+        };
+        Ok((last_modified, Some((results, total))))
+    })
+    .await?;
+
+    let mut response_headers = HeaderMap::new();
+    if let Some(time) = last_modified {
+        response_headers.insert(header::LAST_MODIFIED, http_date(time).parse().unwrap()); // always valid, it's plain ASCII
+    }
+    let Some((results, total)) = results else {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    };
+
+    // an explicit ?format= wins, otherwise a client asking for text/csv (e.g. a spreadsheet export) gets CSV
+    if list_format.format.is_none() && accepts_csv(&headers) {
+        return Ok((response_headers, todos_csv(&headers, &results)).into_response());
+    }
+
+    let data = match fields {
+        Some(fields) => Value::Array(results.iter().map(|todo| only_fields(todo, &fields)).collect()),
+        None => json!(results),
+    };
+    match total {
+        Some(total) => {
+            let envelope = json!({ "data": data, "page": { "limit": limit, "offset": page.offset, "total": total } });
+            Ok((StatusCode::OK, response_headers, Json(envelope)).into_response())
+        }
+        None => Ok((StatusCode::OK, response_headers, Json(data)).into_response()),
+    }
+}
+
+// the page size for a ?limit=: DEFAULT_PAGE_SIZE when there is none, and no more than MAX_PAGE_SIZE; 400 unless positive
+fn page_limit(state: &AppState, limit: Option<i64>) -> Result<i64, StatusCode> {
+    let limit = limit.unwrap_or(state.config.default_page_size).min(state.config.max_page_size);
+    if limit <= 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(limit)
+}
+
+// order a todos query by ?sort= and ?order=, then by id in the same direction: todos can share a created_at (e.g. all of an
+// import's), and without a unique tie-breaker Postgres may return those in any order, so pages could repeat or skip them
+fn sorted(query: todos::BoxedQuery<'static, Pg>, sort: TodoSort, order: SortOrder) -> todos::BoxedQuery<'static, Pg> {
+    // the same for every column, only the column differs
+    macro_rules! by {
+        ($column:expr) => {
+            match order {
+                SortOrder::Asc => query.order($column.asc()).then_order_by(id.asc()),
+                SortOrder::Desc => query.order($column.desc()).then_order_by(id.desc()),
+            }
+        };
+    }
+
+    match sort {
+        TodoSort::Position => by!(todos::position),
+        TodoSort::CreatedAt => by!(todos::created_at),
+        TodoSort::UpdatedAt => by!(todos::updated_at),
+        TodoSort::DueDate => match order {
+            // NULLS LAST either way, rather than Postgres' default of treating a missing due date as the latest one
+            SortOrder::Asc => query.order(todos::due_date.asc().nulls_last()).then_order_by(id.asc()),
+            SortOrder::Desc => query.order(todos::due_date.desc().nulls_last()).then_order_by(id.desc()),
+        },
+        TodoSort::Title => by!(todos::title),
+        TodoSort::Version => by!(todos::version),
+    }
+}
+
+// parse ?fields=id,title into the fields to keep, None when every field is wanted
+// a name that isn't one of TODO_FIELDS is most likely a typo, so it's refused with 400 rather than silently dropped
+fn selected_fields(fields: Option<&str>) -> Result<Option<Vec<String>>, StatusCode> {
+    let Some(fields) = fields else {
+        return Ok(None);
+    };
+
+    let fields: Vec<String> = fields.split(',').map(|field| field.trim().to_string()).collect();
+    if fields.iter().any(|field| !TODO_FIELDS.contains(&field.as_str())) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Some(fields))
+}
+
+// a todo as JSON with only the given fields
+fn only_fields(todo: &Todo, fields: &[String]) -> Value {
+    let Value::Object(mut object) = serde_json::to_value(todo).unwrap() else { // serializing a Todo can't fail
+        unreachable!("a Todo serializes to a JSON object");
+    };
+    object.retain(|key, _| fields.contains(key));
+    Value::Object(object)
+}
+
+/*
+When the list last changed, to whole seconds: the latest updated_at of any todo, or the last delete if it's later
+(a deleted todo isn't in the list anymore, but the list still changed); None for a list that never changed.
+It's taken over every row, soft-deleted ones included, not just the todos a filter picks: a todo that leaves a filtered
+list (completed while a client polls ?completed=false, or archived out of the default list) changes that list too,
+but it's no longer among the todos the filter matches, so their updated_at would never show it.
+*/
+fn list_last_modified(conn: &mut PgConnection) -> QueryResult<Option<DateTime<Utc>>> {
+    let updated = todos::table
+        .select(diesel::dsl::max(todos::updated_at))
+        .first::<Option<DateTime<Utc>>>(conn)?;
+    let deleted = todo_deletions::table
+        .select(todo_deletions::last_deleted_at)
+        .first::<Option<DateTime<Utc>>>(conn)?;
+
+    Ok(updated.max(deleted).map(|time| time.trunc_subsecs(0))) // None sorts before any time
+}
+
+// the If-Modified-Since header as a time, None when it's absent or not a valid HTTP date
+fn if_modified_since(headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let value = headers.get(header::IF_MODIFIED_SINCE)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value).ok().map(|time| time.with_timezone(&Utc))
+}
+
+// format a time as an HTTP date, e.g. Wed, 07 May 2025 15:26:33 GMT
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// whether the Accept header lists text/csv, e.g. Accept: text/csv or Accept: text/csv;q=0.9, application/json
+fn accepts_csv(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| media_range.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("text/csv"))
+}
+
+// quote a CSV field if it contains a comma, quote or line break, doubling any quotes inside it (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// render the todos as a CSV download with a header row and one line per todo
+// the same todos always give the same bytes, so the ETag is a hash of the file, and a download of a big list that broke
+// off can be resumed with a Range (and If-Range: <etag>, to make sure the list didn't change in between), see range.rs
+fn todos_csv(headers: &HeaderMap, todos: &[Todo]) -> Response {
+    let mut csv = String::from("id,title,content,completed,created_at\r\n");
+    for todo in todos {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\r\n",
+            todo.id,
+            csv_field(&todo.title),
+            csv_field(&todo.content),
+            todo.completed,
+            todo.created_at.to_rfc3339(),
+        ));
+    }
+
+    let mut hasher = DefaultHasher::new(); // not seeded per process, so every instance of a build gives the same ETag
+    csv.hash(&mut hasher);
+    let etag = format!("\"csv-{:016x}\"", hasher.finish());
+
+    let mut response = ranged_response(headers, &etag, "text/csv; charset=utf-8", csv.into_bytes());
+    response.headers_mut().insert(header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment; filename=\"todos.csv\""));
+    response
+}
+
+// GET stats
+/*
+Counts for a dashboard widget: {"total": n, "completed": c, "pending": p, "overdue": o}, where overdue means not completed and past its due date.
+All the counts come from a single aggregate query (COUNT(*) with FILTER clauses) rather than loading rows, and the
+(completed, due_date) index covers every column it reads so Postgres can answer it from the index alone.
+*/
+pub async fn todo_stats(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let (total, completed, overdue) = run_db(&state, |conn| {
+        todos::table
+            .filter(live())
+            .select((
+                diesel::dsl::count_star(),
+                sql::<BigInt>("COUNT(*) FILTER (WHERE completed)"),
+                sql::<BigInt>("COUNT(*) FILTER (WHERE NOT completed AND due_date < NOW())"),
+            ))
+            .first::<(i64, i64, i64)>(conn)
+            .map_err(db_error)
+    })
+    .await?;
+
+    Ok((StatusCode::OK, Json(json!({
+        "total": total,
+        "completed": completed,
+        "pending": total - completed,
+        "overdue": overdue,
+    }))))
+}
+
+/*
+Streams the todos as NDJSON (application/x-ndjson), one JSON object per line.
+Rather than loading the whole table, we page through it in batches ordered by id (keyset pagination: each page starts after the last id we sent), so the server only ever holds one batch in memory and the client can process lines as they arrive.
+Unlike the CSV and the JSON export, the stream doesn't do Range requests (it says so with Accept-Ranges: none): its length
+isn't known until the last batch is sent, and serving a slice of it would mean rendering the whole thing in memory first,
+which is what streaming it avoids. A client on a flaky connection can download GET /todos/export instead, which resumes.
+*/
+fn stream_todos(state: AppState, filter: TodoFilter, fields: Option<Vec<String>>) -> Response {
+    // the stream state is the app state (for its pool), the filter, the ?fields= selection, the tenant, and where the next
+    // batch starts: Some(None) for the first one, Some(Some(id)) after the last id sent, None once we've run out of rows
+    // (the body is streamed after the handler, and the tenant middleware, returned, so the tenant has to be carried along)
+    let start = (state, filter, fields, tenant::current(), Some(None));
+    let lines = stream::unfold(start, |(state, filter, fields, tenant, last_id)| async move {
+        let last_id: Option<TodoId> = last_id?; // stop the stream after the final (short) batch
+
+        let batch_filter = filter.clone();
+        let batch = tenant::scope(tenant.clone(), run_db(&state, move |conn| {
+            let mut batch = filtered_todos(&batch_filter);
+            if let Some(last_id) = last_id {
+                batch = batch.filter(id.gt(last_id)); // only the rows after the previous batch
+            }
+            batch
+                .order(id.asc())
+                .limit(NDJSON_BATCH_SIZE)
+                .load::<Todo>(conn)
+                .map_err(db_error)
+        }))
+        .await;
+
+        let todos = match batch {
+            Ok(todos) => todos,
+            // an error mid-stream aborts the response body, so the client sees a truncated download rather than a silently short one
+            Err(status) => return Some((Err(std::io::Error::other(status.to_string())), (state, filter, fields, tenant, None))),
+        };
+
+        if todos.is_empty() {
+            return None; // nothing left to send
+        }
+
+        // a full batch means there may be more rows, a short one means this was the last page
+        let next = if (todos.len() as i64) < NDJSON_BATCH_SIZE { None } else { todos.last().map(|t| Some(t.id)) };
+
+        let mut chunk = String::new();
+        for todo in &todos {
+            let line = match &fields {
+                Some(fields) => only_fields(todo, fields).to_string(),
+                None => serde_json::to_string(todo).unwrap(), // serializing a Todo can't fail
+            };
+            chunk.push_str(&line);
+            chunk.push('\n');
+        }
+
+        Some((Ok(chunk), (state, filter, fields, tenant, next)))
+    });
+
+    ([(header::CONTENT_TYPE, "application/x-ndjson"), (header::ACCEPT_RANGES, "none")], Body::from_stream(lines)).into_response()
+}
+
+// GET board
+/*
+The list split into columns for a kanban board: GET /todos/board returns {"pending": [...], "completed": [...]}, each in the user's order.
+Each column holds at most ?limit= todos (DEFAULT_PAGE_SIZE when absent, at most MAX_PAGE_SIZE), so a long done
+column can't make the response unbounded. Both columns come from one query, a UNION ALL of the two limited halves,
+that is split up again here. Archived todos aren't on the board.
+*/
+pub async fn todo_board(
+    State(state): State<AppState>,
+    Query(board): Query<BoardQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let limit = page_limit(&state, board.limit)?;
+
+    let mut todos = run_db(&state, move |conn| {
+        let column = |completed: bool| {
+            filtered_todos(&TodoFilter { completed: Some(completed), created_after: None, created_before: None, archived: false, ids: None })
+                .order((todos::position.asc(), id.asc()))
+                .limit(limit)
+        };
+        column(false)
+            .union_all(column(true))
+            .load::<Todo>(conn)
+            .map_err(db_error)
+    })
+    .await?;
+
+    // a UNION doesn't promise to keep each half's order, so sort by status and position again before splitting
+    todos.sort_by_key(|todo| (todo.completed, todo.position, todo.id));
+    let (completed, pending): (Vec<Todo>, Vec<Todo>) = todos.into_iter().partition(|todo| todo.completed);
+
+    Ok(Json(json!({ "pending": pending, "completed": completed })))
+}
+
+// GET count
+// Instead of loading every row just to count them, we let the database do a COUNT(*) and return {"count": n}. It accepts the same ?completed= filter as the list endpoint.
+pub async fn count_todos(
+    State(state): State<AppState>,
+    Query(filter): Query<TodoFilter>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    check_filter(&state, &filter)?;
+    let count = run_db(&state, move |conn| {
+        filtered_todos(&filter).count().get_result::<i64>(conn)
+            .map_err(db_error)
+    })
+    .await?;
+
+    Ok((StatusCode::OK, Json(json!({ "count": count }))))
+}
+
+// GET search
+/*
+Full-text search over the title and content: GET /todos/search?q=milk returns the matching todos, best match first.
+q is a Postgres tsquery, so it supports & (and), | (or), ! (not) and :* (prefix), e.g. ?q=milk%20%26%20!oat.
+The match runs against the indexed search_vector column, so it doesn't scan every row like ILIKE '%milk%' would,
+and it matches word forms too (searching "run" finds "running"). Titles count for more than content in the rank.
+A query that isn't valid tsquery syntax (e.g. "milk &") gets 400 Bad Request.
+*/
+pub async fn search_todos(
+    State(state): State<AppState>,
+    Query(search): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchResult>>, StatusCode> {
+    let q = search.q.trim().to_string();
+    if q.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let results = run_db(&state, move |conn| {
+        let query = || to_tsquery(english(), q.clone());
+        let rank = || ts_rank(search_vector(), query());
 
-    let results = todos::table.load::<Todo>(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
+        todos::table
+            .filter(live())
+            .filter(Matches::new(search_vector(), query())) // uses the GIN index on search_vector
+            .select((todos::all_columns, rank(), ts_headline(english(), todos::content, query())))
+            .order((rank().desc(), id.asc()))
+            .load::<(Todo, f32, String)>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(_, info) if info.message().contains("tsquery") => StatusCode::BAD_REQUEST,
+                e => db_error(e),
+            })
+    })
+    .await?;
 
-    (StatusCode::OK, Json(results))
+    let results = results
+        .into_iter()
+        .map(|(todo, rank, highlight)| SearchResult { todo, rank, highlight })
+        .collect();
+    Ok(Json(results))
+}
+
+// GET export
+/*
+A backup of every todo as one JSON document, {"exported_at": ..., "todos": [...]}, in list order.
+POST /todos/import accepts the same document to restore it.
+
+A large export can be resumed with a Range request (see range.rs). That only works if exporting the same todos again
+gives the same bytes, so the ETag is a hash of the todos, and exported_at, the one part that differs between two exports,
+comes first and always has the same width (microseconds, always in UTC): everything after it is then byte for byte
+identical, and a client resuming with If-Range: <etag> gets exactly the rest of the document it had started on.
+*/
+pub async fn export_todos(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, StatusCode> {
+    let todos = run_db(&state, |conn| {
+        todos::table
+            .filter(live())
+            .order((todos::position.asc(), id.asc()))
+            .load::<Todo>(conn)
+            .map_err(db_error)
+    })
+    .await?;
+
+    let todos = serde_json::to_string(&todos).unwrap(); // serializing todos can't fail
+    let exported_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+    let document = format!("{{\"exported_at\":\"{}\",\"todos\":{}}}", exported_at, todos);
+
+    let mut hasher = DefaultHasher::new(); // unlike a HashMap's hasher it isn't seeded per process, so every instance of a build agrees
+    todos.hash(&mut hasher);
+    let etag = format!("\"export-{:016x}\"", hasher.finish());
+
+    Ok(ranged_response(&headers, &etag, "application/json", document.into_bytes()))
+}
+
+// GET changes
+/*
+Incremental sync for clients that can't keep a WebSocket open: GET /todos/changes?since=<RFC 3339 time> returns
+      {"todos": [...], "deleted": [3, 7], "server_time": "...", "next": null}
+with every todo created or updated after `since` (archived ones included) and the ids of the todos deleted since then,
+in updated_at order. Leaving out since returns everything, for a first sync.
+
+A page holds at most CHANGES_PAGE_SIZE changes. When there are more, "next" is {"since": ..., "after_id": ...}
+to pass as the query of the next request. Once "next" is null the client is caught up and keeps "server_time" as its next since.
+Deleted todos are only kept for PURGE_AFTER_DAYS (see purge.rs), a client that was away longer should do a full sync.
+
+updated_at is stamped when a write's transaction starts, so a slow write can commit with an updated_at that's already a
+little in the past. server_time is therefore taken CHANGES_OVERLAP_SECS back, so such a write still shows up next time;
+the price is that a change near the end of a sync can be sent twice, which a client applying changes by id doesn't mind.
+*/
+pub async fn todo_changes(
+    State(state): State<AppState>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let (server_time, changes) = run_db(&state, move |conn| {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            // the database's clock, since that's the one updated_at comes from
+            let server_time = diesel::select(sql::<Timestamptz>("NOW()")).get_result::<DateTime<Utc>>(conn)?;
+
+            let mut changes = todos::table.into_boxed(); // deleted todos too, that's how their ids are found
+            if let Some(since) = query.since {
+                changes = match query.after_id {
+                    // continuing from the previous page, which ended with the todo (since, after_id)
+                    Some(after_id) => changes.filter(todos::updated_at.gt(since).or(todos::updated_at.eq(since).and(id.gt(after_id)))),
+                    None => changes.filter(todos::updated_at.gt(since)),
+                };
+            }
+            let changes = changes
+                .order((todos::updated_at.asc(), id.asc())) // id breaks ties, so a page boundary never splits them ambiguously
+                .limit(CHANGES_PAGE_SIZE)
+                .load::<Todo>(conn)?;
+            Ok((server_time, changes))
+        })
+        .map_err(db_error)
+    })
+    .await?;
+
+    // a full page means there may be more, carry on after its last change
+    let next = match changes.last() {
+        Some(last) if changes.len() as i64 == CHANGES_PAGE_SIZE => json!({ "since": last.updated_at, "after_id": last.id }),
+        _ => Value::Null,
+    };
+
+    let (deleted, todos): (Vec<Todo>, Vec<Todo>) = changes.into_iter().partition(|todo| todo.deleted_at.is_some());
+    let deleted: Vec<TodoId> = deleted.iter().map(|todo| todo.id).collect();
+
+    Ok(Json(json!({
+        "todos": todos,
+        "deleted": deleted,
+        "server_time": server_time - chrono::Duration::seconds(CHANGES_OVERLAP_SECS),
+        "next": next,
+    })))
+}
+
+// POST import
+/*
+Restores a document from GET /todos/export (or any {"todos": [...]} with at least a title and content per record).
+Every record is validated on its own first. The valid ones are then inserted in a single transaction, appended after
+the existing todos in their document order, and the response reports the rest: {"imported": n, "failed": [{"index": i, "error": "..."}]}.
+The imported todos get new ids (an export's ids may already be taken), so links between them like recurred_from are dropped.
+
+With ?mode=replace the existing todos are deleted first, in the same transaction. Since that throws the old list away,
+a replace only goes ahead if every record is valid: otherwise nothing changes and the report comes back with 422.
+
+With ?partial=true every record is saved or rejected on its own, see import_each (it can't be combined with a replace).
+
+With MAX_TODOS_PER_USER set, an import whose valid records don't all fit in the list (after the delete, for a replace)
+is rejected as a whole with 409 and nothing changes, see check_todo_limit.
+*/
+pub async fn import_todos(
+    State(state): State<AppState>,
+    Query(params): Query<ImportParams>,
+    Query(Partial { partial }): Query<Partial>,
+    Json(document): Json<ImportDocument>,
+) -> Result<(StatusCode, Json<Value>), Response> {
+    if partial {
+        if params.mode == ImportMode::Replace {
+            return Err(StatusCode::BAD_REQUEST.into_response()); // a replace is all-or-nothing by definition
+        }
+        return import_each(&state, document.todos).await;
+    }
+
+    let mut valid = Vec::new();
+    let mut failed = Vec::new();
+    for (index, record) in document.todos.into_iter().enumerate() {
+        match validate_import_record(record) {
+            Ok(todo) => valid.push(todo),
+            Err(error) => failed.push(json!({ "index": index, "error": error })),
+        }
+    }
+
+    let replace = params.mode == ImportMode::Replace;
+    if replace && !failed.is_empty() {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "imported": 0, "failed": failed }))));
+    }
+
+    let max_todos = state.config.max_todos_per_user;
+    let (deleted, imported) = with_retry(&state, move |conn| {
+        let mut full = None;
+        let imported = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let deleted = if replace {
+                diesel::update(todos::table.filter(live()))
+                    .set(todos::deleted_at.eq(diesel::dsl::now))
+                    .returning(id)
+                    .get_results::<TodoId>(conn)?
+            } else {
+                Vec::new()
+            };
+
+            if valid.is_empty() {
+                return Ok((deleted, Vec::new()));
+            }
+            if let Err(limit) = check_todo_limit(conn, max_todos, valid.len())? {
+                full = Some(limit);
+                return Err(diesel::result::Error::RollbackTransaction); // an error rolls back a replace's delete too
+            }
+            let start = next_position(conn)?;
+            let rows: Vec<_> = valid
+                .iter()
+                .zip(start..)
+                .map(|(todo, position)| (todo, todos::position.eq(position)))
+                .collect();
+            let imported = diesel::insert_into(todos::table).values(rows).get_results::<Todo>(conn)?;
+            Ok((deleted, imported))
+        });
+        match (imported, full) {
+            (Err(diesel::result::Error::RollbackTransaction), Some(full)) => Ok(Err(full)),
+            (imported, _) => imported.map(Ok),
+        }
+    })
+    .await
+    .map_err(IntoResponse::into_response)?
+    .map_err(IntoResponse::into_response)?;
+    state.invalidate_all_todos(); // in replace mode, every cached todo is gone
+    for deleted_id in deleted {
+        state.publish(TodoEvent::Deleted { id: deleted_id });
+    }
+    let count = imported.len();
+    for todo in imported {
+        state.publish(TodoEvent::Created { todo });
+    }
+
+    Ok((StatusCode::OK, Json(json!({ "imported": count, "failed": failed }))))
+}
+
+/*
+POST /todos/import?partial=true, for an importer that wants to keep the good records of a batch and skip the bad ones:
+each record is validated and inserted on its own (in a savepoint, so one the database rejects doesn't take the others
+with it), and the answer is 207 Multi-Status with a result per record, in document order:
+      [{"index": 0, "status": "ok", "id": 12}, {"index": 1, "status": "error", "reason": "missing field `title`"}, ...]
+A batch whose valid records don't all fit in the list (MAX_TODOS_PER_USER) is still rejected as a whole, with 409.
+*/
+async fn import_each(state: &AppState, records: Vec<Value>) -> Result<(StatusCode, Json<Value>), Response> {
+    let records: Vec<Result<ImportTodo, String>> = records.into_iter().map(validate_import_record).collect();
+    let valid = records.iter().filter(|record| record.is_ok()).count();
+    let max_todos = state.config.max_todos_per_user;
+
+    let (results, imported) = with_retry(state, move |conn| {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            if let Err(full) = check_todo_limit(conn, max_todos, valid)? {
+                return Ok(Err(full)); // nothing was written yet
+            }
+            let mut results = Vec::new();
+            let mut imported = Vec::new();
+            for (index, record) in records.iter().enumerate() {
+                let todo = match record {
+                    Ok(todo) => todo,
+                    Err(error) => {
+                        results.push(item_error(index, error));
+                        continue;
+                    }
+                };
+                let inserted = conn.transaction(|conn| {
+                    let position = next_position(conn)?;
+                    diesel::insert_into(todos::table).values((todo, todos::position.eq(position))).get_result::<Todo>(conn)
+                });
+                match inserted {
+                    Ok(todo) => {
+                        results.push(item_ok(index, todo.id));
+                        imported.push(todo);
+                    }
+                    Err(e) if is_transient(&e) => return Err(e), // not this record's fault, the whole batch is tried again
+                    Err(_) => results.push(item_error(index, "could not be saved")),
+                }
+            }
+            Ok(Ok((results, imported)))
+        })
+    })
+    .await
+    .map_err(IntoResponse::into_response)?
+    .map_err(IntoResponse::into_response)?;
+    for todo in imported {
+        state.publish(TodoEvent::Created { todo });
+    }
+
+    Ok((StatusCode::MULTI_STATUS, Json(Value::Array(results))))
+}
+
+// one item's entry in a ?partial=true answer, for an item that went through
+fn item_ok(index: usize, todo_id: TodoId) -> Value {
+    json!({ "index": index, "status": "ok", "id": todo_id })
+}
+
+// and for one that didn't, with why
+fn item_error(index: usize, reason: &str) -> Value {
+    json!({ "index": index, "status": "error", "reason": reason })
+}
+
+// POST import-file
+/*
+For backups too big to send as one JSON document: POST /todos/import-file with a multipart/form-data body whose "file"
+field is a JSON-lines file, one todo per line in the same shape as an import record (e.g. GET /todos?format=ndjson saved to disk).
+The file is read as it arrives, a chunk at a time, and the todos are inserted IMPORT_FILE_BATCH_SIZE at a time, appended after
+the existing ones in file order, so memory use doesn't grow with the file. Each batch is its own transaction: if the import
+fails halfway (e.g. the client disconnects), the batches before that stay imported.
+A line that doesn't parse is skipped and reported rather than failing the rest, the response sums it all up:
+{"imported": n, "failed": [{"line": 3, "error": "..."}], "failed_total": m}, with line numbers starting at 1
+and at most IMPORT_FILE_MAX_REPORTED failures listed (failed_total counts them all). Blank lines are ignored.
+A file larger than IMPORT_FILE_MAX_BYTES (default 50 MB) is cut off with 413 Payload Too Large, a body without a "file" field gets 422.
+Like any request, the whole upload has to finish within REQUEST_TIMEOUT_SECS.
+With MAX_TODOS_PER_USER set, the first batch that doesn't fit in the list stops the import with 409 (see check_todo_limit),
+the batches before it stay imported like after any other failure.
+*/
+pub async fn import_file(State(state): State<AppState>, mut multipart: Multipart) -> Result<Json<Value>, Response> {
+    let mut file = loop {
+        match multipart.next_field().await.map_err(|e| e.status().into_response())? {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue, // some other form field, not ours
+            None => return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response()),
+        }
+    };
+
+    let mut import = FileImport::default();
+    let mut pending = Vec::new(); // the start of a line whose end hasn't arrived yet
+    while let Some(chunk) = file.chunk().await.map_err(|e| e.status().into_response())? { // 413 once the file goes over the limit
+        pending.extend_from_slice(&chunk);
+
+        let mut start = 0;
+        while let Some(length) = pending[start..].iter().position(|&byte| byte == b'\n') {
+            import.add_line(&pending[start..start + length]);
+            start += length + 1;
+            if import.batch.len() == IMPORT_FILE_BATCH_SIZE {
+                import.imported += insert_import_batch(&state, std::mem::take(&mut import.batch)).await?;
+            }
+        }
+        pending.drain(..start);
+    }
+    import.add_line(&pending); // the last line, if the file doesn't end with a newline
+    if !import.batch.is_empty() {
+        import.imported += insert_import_batch(&state, std::mem::take(&mut import.batch)).await?;
+    }
+
+    Ok(Json(json!({ "imported": import.imported, "failed": import.failed, "failed_total": import.failed_total })))
+}
+
+// how far one POST /todos/import-file has got through its file
+#[derive(Default)]
+struct FileImport {
+    lines: usize, // how many lines have been read
+    batch: Vec<ImportTodo>, // the todos parsed since the last batch was inserted
+    imported: usize, // how many todos the inserted batches held
+    failed: Vec<Value>, // the first IMPORT_FILE_MAX_REPORTED lines that didn't parse, and why
+    failed_total: usize, // how many lines didn't parse in all
+}
+
+impl FileImport {
+    // parse the next line of the file into the batch, or into the failures if it isn't a valid todo
+    fn add_line(&mut self, line: &[u8]) {
+        self.lines += 1;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return; // a blank line (\r\n line endings leave a \r, which is just whitespace to serde_json)
+        }
+
+        match serde_json::from_slice::<ImportTodo>(line) {
+            Ok(todo) => self.batch.push(todo),
+            Err(e) => {
+                self.failed_total += 1;
+                if self.failed.len() < IMPORT_FILE_MAX_REPORTED {
+                    self.failed.push(json!({ "line": self.lines, "error": e.to_string() }));
+                }
+            }
+        }
+    }
+}
+
+// how many todos POST /todos/import-file inserts at a time
+const IMPORT_FILE_BATCH_SIZE: usize = 500;
+
+// how many failed lines the import-file report lists at most, so a file that's all garbage doesn't give a giant response
+const IMPORT_FILE_MAX_REPORTED: usize = 1000;
+
+// append one batch of imported todos after the existing ones, returning how many were inserted
+async fn insert_import_batch(state: &AppState, batch: Vec<ImportTodo>) -> Result<usize, Response> {
+    let max_todos = state.config.max_todos_per_user;
+    let imported = with_retry(state, move |conn| {
+        conn.transaction(|conn| {
+            if let Err(full) = check_todo_limit(conn, max_todos, batch.len())? {
+                return Ok(Err(full));
+            }
+            let start = next_position(conn)?;
+            let rows: Vec<_> = batch.iter().zip(start..).map(|(todo, position)| (todo, todos::position.eq(position))).collect();
+            diesel::insert_into(todos::table).values(rows).get_results::<Todo>(conn).map(Ok)
+        })
+    })
+    .await
+    .map_err(IntoResponse::into_response)?
+    .map_err(IntoResponse::into_response)?;
+    let count = imported.len();
+    for todo in imported {
+        state.publish(TodoEvent::Created { todo });
+    }
+    Ok(count)
+}
+
+// check one import record, returning why it can't be imported if it can't
+fn validate_import_record(record: Value) -> Result<ImportTodo, String> {
+    serde_json::from_value::<ImportTodo>(record).map_err(|e| e.to_string()) // e.g. missing field `title`, or title must not be empty
+}
+
+// ADMIN read-only
+// Turns read-only mode (see read_only_guard in middleware.rs) on or off at runtime: POST /admin/read-only with {"read_only": true}.
+// Like every /admin endpoint it's only for admins, see the admin routes in lib.rs and auth.rs.
+pub async fn set_read_only(
+    State(state): State<AppState>,
+    Json(toggle): Json<ReadOnlyToggle>,
+) -> Result<Json<Value>, StatusCode> {
+    state.set_read_only(toggle.read_only);
+    tracing::info!(read_only = toggle.read_only, "read-only mode changed");
+    Ok(Json(json!({ "read_only": toggle.read_only })))
+}
+
+
+// ADMIN reset
+/*
+Development only: POST /admin/reset empties the database and restarts the id sequences, returning {"deleted": n}.
+It saves dropping to psql between manual or automated test runs. The route is only registered when ALLOW_DEV_ENDPOINTS=true
+(see lib.rs), everywhere else it doesn't exist and gets 404. Even then it's only for admins, like every /admin endpoint.
+TRUNCATE skips the delete trigger that records when the list last changed, so that's updated here to keep Last-Modified right.
+*/
+pub async fn reset_database(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let deleted = run_db(&state, |conn| {
+        conn.transaction::<Vec<TodoId>, diesel::result::Error, _>(|conn| {
+            let ids = todos::table.filter(live()).select(id).load::<TodoId>(conn)?; // for the count and the change events
+            diesel::sql_query("TRUNCATE todos, attachments RESTART IDENTITY").execute(conn)?;
+            diesel::update(todo_deletions::table).set(todo_deletions::last_deleted_at.eq(diesel::dsl::now)).execute(conn)?;
+            Ok(ids)
+        })
+        .map_err(db_error)
+    })
+    .await?;
+    state.invalidate_all_todos();
+    for &deleted_id in &deleted {
+        state.publish(TodoEvent::Deleted { id: deleted_id });
+    }
+    tracing::warn!(deleted = deleted.len(), "database reset");
+
+    Ok(Json(json!({ "deleted": deleted.len() })))
+}
+
+// GET version
+// Which build is running, for checking that a deployment actually rolled out: the crate version from Cargo.toml,
+// plus the git commit and build time that build.rs injects at compile time.
+pub async fn get_version() -> Json<Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("GIT_SHA"),
+        "build_time": env!("BUILD_TIME"),
+    }))
 }
 
 // GET todo id
 // We get the todo id from path params and do a query to todos table by filtering id as follows
+// The todo's current version and position are returned in the ETag header so a client can send it back in If-Match when updating
+// When the cache is enabled, a cached copy is served without touching the database, and a missed todo is cached once read
+// ?include=attachments embeds the todo's attachments under "attachments" (always read from the database, they aren't cached)
+/*
+Caching: the response says Cache-Control: private, max-age=0, must-revalidate, so a browser (but no shared cache) may keep it
+but has to check back before reusing it. It does that by sending the ETag back in If-None-Match, and while the todo hasn't
+changed it gets an empty 304 Not Modified instead of the todo again.
+With ?include=attachments the ETag also covers the attachments (see attachments_etag), since adding or removing one
+doesn't change the todo's version.
+*/
 pub async fn get_todo(
-    Path(todo_id): Path<i32>,
-    State(db): State<DbPool>,
-) -> (StatusCode,Json<Todo>) {
-    let mut conn = db.get().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+    Query(include): Query<TodoInclude>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let include_attachments = match include.include.as_deref() {
+        None => false,
+        Some(include) => {
+            if include.split(',').any(|part| part.trim() != "attachments") {
+                return Err(StatusCode::BAD_REQUEST); // nothing else can be included
+            }
+            true
+        }
+    };
 
-    let result = todos::table.filter(id.eq(todo_id)).first::<Todo>(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
+    let tenant = tenant::current();
+    let cached = state.cache.as_ref().and_then(|cache| cache.get(tenant.as_deref(), todo_id));
 
-    (StatusCode::OK, Json(result))
+    let result = match cached {
+        Some(todo) => todo,
+        None => {
+            let generation = state.cache.as_ref().map(|cache| cache.generation()); // taken before the read, see TodoCache
+
+            let todo = run_db(&state, move |conn| {
+                todos::table.filter(id.eq(todo_id)).filter(live()).first::<Todo>(conn)
+                    .map_err(query_error) // 404 if there is no todo with this id
+            })
+            .await?;
+
+            if let (Some(cache), Some(generation)) = (&state.cache, generation) {
+                cache.insert(tenant.as_deref(), todo.clone(), generation);
+            }
+            todo
+        }
+    };
+
+    let attachments = if include_attachments {
+        let attachments = run_db(&state, move |conn| {
+            attachments::table
+                .filter(attachments::todo_id.eq(todo_id))
+                .order(attachments::id.asc())
+                .load::<Attachment>(conn)
+                .map_err(db_error)
+        })
+        .await?;
+        Some(attachments)
+    } else {
+        None
+    };
+
+    let tag = match &attachments {
+        Some(attachments) => attachments_etag(&result, attachments),
+        None => etag(&result),
+    };
+    let cache_headers = [
+        (header::ETAG, tag.clone()),
+        (header::CACHE_CONTROL, "private, max-age=0, must-revalidate".to_string()),
+    ];
+    if if_none_match(&headers, &tag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    if let Some(attachments) = attachments {
+        let mut body = serde_json::to_value(&result).unwrap(); // serializing a Todo can't fail
+        body["attachments"] = json!(attachments);
+        return Ok((StatusCode::OK, cache_headers, Json(body)).into_response());
+    }
+
+    Ok((StatusCode::OK, cache_headers, Json(result)).into_response())
+}
+
+// the ETag of a todo together with its attachments: its version and position, how many attachments it has and the newest one's id
+// adding an attachment raises the newest id, removing one lowers the count, so either changes the tag
+fn attachments_etag(todo: &Todo, attachments: &[Attachment]) -> String {
+    let newest = attachments.iter().map(|attachment| attachment.id).max().unwrap_or(0);
+    format!("\"{}-{}-{}-{}\"", todo.version, todo.position, attachments.len(), newest)
+}
+
+// ADD attachment
+/*
+Attaches a link to a todo: POST /todos/{id}/attachments with {"url": "https://...", "label": "optional"} returns the new attachment with 201.
+Only absolute http:// and https:// URLs are accepted, anything else (a typo, javascript:, a relative path, ...) gets 422.
+404 if there is no todo with this id.
+*/
+pub async fn add_attachment(
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+    Json(new_attachment): Json<NewAttachment>,
+) -> Result<(StatusCode, Json<Attachment>), StatusCode> {
+    if !is_web_url(&new_attachment.url) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let attachment = with_retry(&state, move |conn| {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let exists = diesel::select(diesel::dsl::exists(todos::table.filter(id.eq(todo_id)).filter(live()))).get_result::<bool>(conn)?;
+            if !exists {
+                return Err(diesel::result::Error::NotFound);
+            }
+
+            diesel::insert_into(attachments::table)
+                .values((&new_attachment, attachments::todo_id.eq(todo_id)))
+                .get_result::<Attachment>(conn)
+        }) // NotFound (404) if there is no todo with this id
+    })
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(attachment)))
+}
+
+// whether a string is a well-formed absolute http(s) URL with a host, e.g. https://example.com/file.pdf
+fn is_web_url(value: &str) -> bool {
+    match url::Url::parse(value) {
+        Ok(url) => matches!(url.scheme(), "http" | "https") && url.host_str().is_some_and(|host| !host.is_empty()),
+        Err(_) => false,
+    }
+}
+
+// REMOVE attachment
+// DELETE /todos/{id}/attachments/{attachment_id}, 404 if the todo has no attachment with that id
+pub async fn remove_attachment(
+    IdPath((todo_id, attachment_id)): IdPath<(TodoId, i32)>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = with_retry(&state, move |conn| {
+        diesel::delete(
+            attachments::table
+                .filter(attachments::id.eq(attachment_id))
+                .filter(attachments::todo_id.eq(todo_id)) // an attachment can only be removed through its own todo
+                .filter(diesel::dsl::exists(todos::table.filter(id.eq(todo_id)).filter(live()))), // which hasn't been deleted
+        )
+        .execute(conn)
+    })
+    .await?;
+
+    if deleted == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
 }
 
 // UPDATE
 // In this handler, we accept update payload from end user and update existing Todo by resolving the id from path params.
+/*
+Every update bumps the version column. If the client sends an If-Match header, the update only applies when the
+todo is still at one of the listed ETags (version and position); otherwise someone else changed or moved it in the
+meantime and we return 412 Precondition Failed instead of overwriting their change. The check is part of the UPDATE's
+WHERE clause, so two concurrent updates can't both pass it.
+*/
 pub async fn update_todo(
-    Path(todo_id): Path<i32>,
-    State(db): State<DbPool>,
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(update_todo): Json<UpdateTodo>,
-) -> (StatusCode,Json<Todo>) {
-    let mut conn = db.get().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
+) -> Result<impl IntoResponse, StatusCode> {
+    let tags = if_match_tags(&headers);
+
+    // the query's outcome is itself a Result, the 404 or 412 for a todo that exists but wasn't updated
+    let todo = with_retry(&state, move |conn| {
+        let mut target = diesel::update(todos::table).filter(id.eq(todo_id)).filter(live()).into_boxed();
+        if let Some(tags) = &tags {
+            // only update the version the client last saw, at the position it last saw it; FALSE when no tag was ours
+            let none: Box<dyn BoxableExpression<todos::table, Pg, SqlType = Bool>> = Box::new(sql::<Bool>("FALSE"));
+            let seen = tags.iter().fold(none, |seen, &(version, position)| {
+                Box::new(seen.or(todos::version.eq(version).and(todos::position.eq(position))))
+            });
+            target = target.filter(seen);
+        }
 
-    let todo = diesel::update(todos::table.filter(id.eq(todo_id)))
-        .set(&update_todo)
-        .get_result(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
+        let todo = target
+            .set((&update_todo, todos::version.eq(todos::version + 1)))
+            .get_result::<Todo>(conn)
+            .optional()?;
 
-    (StatusCode::OK, Json(todo))
+        match todo {
+            Some(todo) => Ok(Ok(todo)),
+            None => {
+                // nothing was updated: either the todo doesn't exist (404) or its version or position didn't match the If-Match header (412)
+                let exists = diesel::select(diesel::dsl::exists(todos::table.filter(id.eq(todo_id)).filter(live())))
+                    .get_result::<bool>(conn)?;
+                Ok(Err(if exists { StatusCode::PRECONDITION_FAILED } else { StatusCode::NOT_FOUND }))
+            }
+        }
+    })
+    .await??;
+    state.invalidate_todo(todo_id); // the cached copy is now out of date
+    state.publish(TodoEvent::Updated { todo: todo.clone() });
+
+    Ok((StatusCode::OK, [(header::ETAG, etag(&todo))], Json(todo)))
+}
+
+// COMPLETE many
+/*
+Backs "select all -> mark done": POST /todos/complete with {"ids": [1, 2, 3]} marks all of them completed
+in a single UPDATE ... WHERE id = ANY($1) and returns {"updated": n}.
+Ids that don't match a todo are skipped rather than failing the whole batch, so n can be smaller than the list.
+An empty list is almost certainly a client bug, so it's rejected with 422 Unprocessable Entity.
+With ?partial=true the answer is 207 Multi-Status with a result per id instead, in the order they were sent,
+"ok" for the ones that were completed and an error for the ones that don't match a todo (see import_each for the shape).
+*/
+pub async fn complete_todos(
+    State(state): State<AppState>,
+    Query(Partial { partial }): Query<Partial>,
+    Json(batch): Json<TodoIds>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if batch.ids.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let ids = batch.ids.clone();
+    let updated = with_retry(&state, move |conn| {
+        diesel::update(todos::table.filter(id.eq_any(&batch.ids)).filter(live())) // eq_any becomes id = ANY($1) with the ids bound as one array
+            .set((todos::completed.eq(true), todos::version.eq(todos::version + 1)))
+            .get_results::<Todo>(conn)
+    })
+    .await?;
+    state.invalidate_all_todos(); // any number of cached todos may have changed
+    let count = updated.len();
+    let updated_ids: Vec<TodoId> = updated.iter().map(|todo| todo.id).collect();
+    for todo in updated {
+        state.publish(TodoEvent::Updated { todo });
+    }
+
+    if partial {
+        let results = ids
+            .into_iter()
+            .enumerate()
+            .map(|(index, todo_id)| {
+                if updated_ids.contains(&todo_id) { item_ok(index, todo_id) } else { item_error(index, "not found") }
+            })
+            .collect();
+        return Ok((StatusCode::MULTI_STATUS, Json(Value::Array(results))));
+    }
+    Ok((StatusCode::OK, Json(json!({ "updated": count }))))
+}
+
+// ARCHIVE and UNARCHIVE
+// Archiving moves a todo out of the active list (see filtered_todos) without deleting it, e.g. once it's completed and no
+// longer interesting. An archived todo keeps its position and can still be fetched and edited, unarchiving brings it back.
+pub async fn archive_todo(
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+) -> Result<Json<Todo>, StatusCode> {
+    set_archived(&state, todo_id, true).await.map(Json)
+}
+
+pub async fn unarchive_todo(
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+) -> Result<Json<Todo>, StatusCode> {
+    set_archived(&state, todo_id, false).await.map(Json)
+}
+
+async fn set_archived(state: &AppState, todo_id: TodoId, archived: bool) -> Result<Todo, StatusCode> {
+    let todo = with_retry(state, move |conn| {
+        diesel::update(todos::table.filter(id.eq(todo_id)).filter(live()))
+            .set((todos::archived.eq(archived), todos::version.eq(todos::version + 1)))
+            .get_result::<Todo>(conn) // NotFound (404) if there is no todo with this id
+    })
+    .await?;
+    state.invalidate_todo(todo_id);
+    state.publish(TodoEvent::Updated { todo: todo.clone() });
+
+    Ok(todo)
+}
+
+// DUPLICATE
+/*
+Clones an existing todo as a template: the copy gets the same content, " (copy)" appended to its title,
+and starts out as a fresh todo (not completed, first version, at the end of the list).
+In a list that's full (MAX_TODOS_PER_USER) it's 409 Conflict, see check_todo_limit.
+*/
+pub async fn duplicate_todo(
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<Todo>), Response> {
+    let max_todos = state.config.max_todos_per_user;
+    let todo = with_retry(&state, move |conn| {
+        conn.transaction(|conn| {
+            let source = todos::table.filter(id.eq(todo_id)).filter(live()).first::<Todo>(conn)?; // NotFound (404) if there is no todo with this id
+            if let Err(full) = check_todo_limit(conn, max_todos, 1)? {
+                return Ok(Err(full));
+            }
+
+            let copy = NewTodo {
+                title: format!("{} (copy)", source.title),
+                content: source.content,
+                due_date: source.due_date,
+                recurrence: source.recurrence,
+            };
+            let position = next_position(conn)?;
+
+            // completed and version are left out so they get their defaults
+            diesel::insert_into(todos::table)
+                .values((&copy, todos::position.eq(position)))
+                .get_result::<Todo>(conn)
+                .map(Ok)
+        })
+    })
+    .await
+    .map_err(IntoResponse::into_response)?
+    .map_err(IntoResponse::into_response)?;
+
+    state.publish(TodoEvent::Created { todo: todo.clone() });
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+// MOVE
+/*
+Drag-and-drop reordering: moves the todo to a new position and shifts the todos in between up or down by one,
+so positions stay contiguous (0, 1, 2, ...). It all happens in one transaction so the list is never left half-shifted.
+Moving up shifts the todos between the new and old position down, moving down shifts the ones in between up.
+Two moves at once (say, from two open tabs) could both shift the same todos and leave two of them at one position, so
+positions are unique: the move that commits second fails, and is run again from fresh reads (see with_retry).
+If it keeps colliding, the client gets 409 Conflict and can reload the list.
+A move isn't an edit, so it leaves versions alone, but the ETag includes the position (see etag), so a cached copy of
+the moved todo or of one that shifted, revalidated with If-None-Match, isn't answered with 304.
+*/
+pub async fn move_todo(
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+    Json(move_todo): Json<MoveTodo>,
+) -> Result<(StatusCode, Json<Todo>), StatusCode> {
+    if move_todo.position < 0 {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY); // positions start at 0
+    }
+
+    let todo = with_retry(&state, move |conn| {
+        conn.transaction::<Todo, diesel::result::Error, _>(|conn| {
+            let current = todos::table.filter(id.eq(todo_id)).filter(live()).select(todos::position).first::<i32>(conn)?;
+            let last = todos::table.filter(live()).select(diesel::dsl::max(todos::position)).first::<Option<i32>>(conn)?.unwrap_or(0);
+            let target = move_todo.position.min(last); // past the end means the end
+
+            if target < current {
+                diesel::update(todos::table.filter(live()).filter(todos::position.ge(target)).filter(todos::position.lt(current)))
+                    .set(todos::position.eq(todos::position + 1))
+                    .execute(conn)?;
+            } else if target > current {
+                diesel::update(todos::table.filter(live()).filter(todos::position.gt(current)).filter(todos::position.le(target)))
+                    .set(todos::position.eq(todos::position - 1))
+                    .execute(conn)?;
+            }
+
+            diesel::update(todos::table.filter(id.eq(todo_id)))
+                .set(todos::position.eq(target))
+                .get_result(conn)
+        }) // NotFound (404) if there is no todo with this id
+    })
+    .await?;
+    state.invalidate_all_todos(); // the todos in between changed position too
+    state.publish(TodoEvent::Updated { todo: todo.clone() });
+
+    Ok((StatusCode::OK, Json(todo)))
 }
 
 // DELETE
-// As you guess, we resolve todo id from path params then execute delete query against todo table as follows.
+// As you guess, we resolve todo id from path params then mark the todo deleted (a soft delete, see live()) as follows.
+// The todos below the deleted one move up by one so positions stay contiguous.
+// With ?dry_run=true nothing is deleted, we answer 200 with what would have been, see dry_run_response().
 pub async fn delete_todo(
-    Path(todo_id): Path<i32>,
-    State(db): State<DbPool>,
-) -> StatusCode {
-    let mut conn = db.get().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
-
-    // this declaration is to ignore the variable
-    let _ =diesel::delete(todos::table.filter(id.eq(todo_id))) 
-        .execute(&mut conn)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR).unwrap();
-
-    StatusCode::NO_CONTENT
-}
\ No newline at end of file
+    IdPath(todo_id): IdPath<TodoId>,
+    State(state): State<AppState>,
+    Query(DryRun { dry_run }): Query<DryRun>,
+) -> Result<Response, StatusCode> {
+    let deleted = with_retry(&state, move |conn| {
+        transaction_or_dry_run(conn, dry_run, |conn| {
+            let deleted = diesel::update(todos::table.filter(id.eq(todo_id)).filter(live()))
+                .set(todos::deleted_at.eq(diesel::dsl::now))
+                .get_result::<Todo>(conn)?; // NotFound if there was no todo with this id
+
+            diesel::update(todos::table.filter(live()).filter(todos::position.gt(deleted.position)))
+                .set(todos::position.eq(todos::position - 1))
+                .execute(conn)?;
+            Ok(deleted)
+        }) // NotFound (404) if there is no todo with this id
+    })
+    .await?;
+    if dry_run {
+        return Ok(dry_run_response(vec![deleted]));
+    }
+    state.invalidate_all_todos(); // the deleted todo is gone and the ones below it changed position
+    state.publish(TodoEvent::Deleted { id: todo_id });
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// DELETE all
+/*
+Backs the "Clear completed" button: DELETE /todos?completed=true removes every completed todo in a single query and returns {"deleted": n}.
+It takes the same filters as the list endpoint, except ?ids= (400, listed todos are deleted with POST /todos/delete). Without any filter this would delete every active (not archived) todo, so we refuse (400) unless ?confirm=true is passed as well.
+The remaining todos are renumbered afterwards so positions stay contiguous.
+With ?dry_run=true nothing is deleted, we answer with what would have been, see dry_run_response() (?confirm=true is still needed without a filter).
+*/
+pub async fn clear_todos(
+    State(state): State<AppState>,
+    Query(filter): Query<TodoFilter>,
+    Query(confirm): Query<ClearConfirm>,
+    Query(DryRun { dry_run }): Query<DryRun>,
+) -> Result<Response, StatusCode> {
+    check_filter(&state, &filter)?;
+    if filter.ids.is_some() {
+        return Err(StatusCode::BAD_REQUEST); // deleting listed todos is POST /todos/delete's job, see delete_todos
+    }
+    let unfiltered = filter.completed.is_none() && filter.created_after.is_none() && filter.created_before.is_none() && !filter.archived;
+    if unfiltered && confirm.confirm != Some(true) {
+        return Err(StatusCode::BAD_REQUEST); // no filter and no confirmation, don't wipe the whole list
+    }
+
+    let deleted = with_retry(&state, move |conn| {
+        transaction_or_dry_run(conn, dry_run, |conn| {
+            // delete whatever the list endpoint would return for the same filters (every active todo when unfiltered, confirmed above)
+            let deleted = diesel::update(todos::table.filter(id.eq_any(filtered_todos(&filter).select(id))))
+                .set(todos::deleted_at.eq(diesel::dsl::now))
+                .get_results::<Todo>(conn)?; // the deleted todos, for the change events (or the dry run's report)
+            renumber_positions(conn)?;
+            Ok(deleted)
+        })
+    })
+    .await?;
+    if dry_run {
+        return Ok(dry_run_response(deleted));
+    }
+    state.invalidate_all_todos(); // todos were deleted and the rest renumbered
+    for todo in &deleted {
+        state.publish(TodoEvent::Deleted { id: todo.id });
+    }
+
+    Ok(Json(json!({ "deleted": deleted.len() })).into_response())
+}
+
+// DELETE many
+/*
+Backs "select all -> delete": POST /todos/delete with {"ids": [1, 2, 3]} deletes all of them in a single
+UPDATE ... WHERE id = ANY($1) (a soft delete, like DELETE /todos/{id}) and returns {"deleted": n}.
+Like POST /todos/complete, ids that don't match one of the caller's todos (with MULTI_TENANT=true only the tenant's own
+are visible at all) are skipped, and an empty list is rejected with 422 Unprocessable Entity.
+The remaining todos are renumbered afterwards so positions stay contiguous, and ?dry_run=true works as for the other deletes.
+*/
+pub async fn delete_todos(
+    State(state): State<AppState>,
+    Query(DryRun { dry_run }): Query<DryRun>,
+    Json(batch): Json<TodoIds>,
+) -> Result<Response, StatusCode> {
+    if batch.ids.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let deleted = with_retry(&state, move |conn| {
+        transaction_or_dry_run(conn, dry_run, |conn| {
+            let deleted = diesel::update(todos::table.filter(id.eq_any(&batch.ids)).filter(live()))
+                .set(todos::deleted_at.eq(diesel::dsl::now))
+                .get_results::<Todo>(conn)?;
+            renumber_positions(conn)?;
+            Ok(deleted)
+        })
+    })
+    .await?;
+    if dry_run {
+        return Ok(dry_run_response(deleted));
+    }
+    state.invalidate_all_todos(); // todos were deleted and the rest renumbered
+    for todo in &deleted {
+        state.publish(TodoEvent::Deleted { id: todo.id });
+    }
+
+    Ok(Json(json!({ "deleted": deleted.len() })).into_response())
+}
+
+// the answer to a ?dry_run=true delete: {"dry_run": true, "deleted": n, "todos": [...]}, the todos that would have been deleted
+// in list order, so a client can show what a delete is about to remove
+fn dry_run_response(mut todos: Vec<Todo>) -> Response {
+    todos.sort_by_key(|todo| todo.position);
+    Json(json!({ "dry_run": true, "deleted": todos.len(), "todos": todos })).into_response()
+}
@@ -0,0 +1,209 @@
+use std::any::Any;
+use std::time::Duration;
+use axum::body::Body;
+use axum::extract::DefaultBodyLimit; // the largest request body an extractor reads, 2 MB unless a route says otherwise
+use axum::http::{header, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::middleware::{from_fn, from_fn_with_state}; // runs one of our own async fns (see middleware.rs) as a layer
+use axum::{Json, Router};
+use axum::routing::{ delete, get, post, put };
+use serde_json::json;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::TraceLayer;
+
+// the app's modules are public so both main.rs and the integration tests in tests/ can use them
+pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod events;
+pub mod extract;
+pub mod handlers;
+pub mod metrics;
+pub mod middleware;
+pub mod models;
+pub mod purge;
+pub mod range;
+pub mod retry;
+pub mod scheduler;
+pub mod schema;
+pub mod search;
+pub mod state;
+pub mod telemetry;
+pub mod tenant;
+
+use state::AppState;
+
+// Builds the full application router (routes, state and middleware) for the given state.
+// main.rs serves this router, and the integration tests call it with a test database pool so they exercise the exact same app.
+pub fn app(state: AppState) -> Router {
+    let request_timeout = state.config.request_timeout;
+    let import_file_max_bytes = state.config.import_file_max_bytes;
+
+    let routes = Router::new() // creates an Axum router
+        // define API routes for handling todos using HTTP methods (GET, POST, DELETE)
+        .route("/todos", post(handlers::create_todo)) // (POST) calls handlers::create_todo
+        .route("/todos", get(handlers::get_todos)) // (GET) calls handlers::get_todos
+        .route("/todos", delete(handlers::clear_todos)) // (DELETE) calls handlers::clear_todos
+        .route("/todos/count", get(handlers::count_todos)) // (GET) calls handlers::count_todos
+        .route("/todos/stats", get(handlers::todo_stats)) // (GET) calls handlers::todo_stats
+        .route("/todos/search", get(handlers::search_todos)) // (GET) calls handlers::search_todos
+        .route("/todos/export", get(handlers::export_todos)) // (GET) calls handlers::export_todos
+        .route("/todos/import", post(handlers::import_todos)) // (POST) calls handlers::import_todos
+        .route("/todos/import-file", post(handlers::import_file).layer(DefaultBodyLimit::max(import_file_max_bytes))) // (POST) calls handlers::import_file, with its own size limit
+        .route("/todos/complete", post(handlers::complete_todos)) // (POST) calls handlers::complete_todos
+        .route("/todos/delete", post(handlers::delete_todos)) // (POST) calls handlers::delete_todos
+        .route("/todos/changes", get(handlers::todo_changes)) // (GET) calls handlers::todo_changes
+        .route("/todos/board", get(handlers::todo_board)) // (GET) calls handlers::todo_board
+        .route("/todos/external/{external_id}", put(handlers::upsert_todo)) // (PUT) calls handlers::upsert_todo
+        .route("/todos/{id}", get(handlers::get_todo)) // (GET) calls handlers::get_todo
+        .route("/todos/{id}", post(handlers::update_todo)) // (POST) calls handlers::update_todo
+        .route("/todos/{id}", delete(handlers::delete_todo)) // (DELETE) calls handlers::delete_todo
+        .route("/todos/{id}/duplicate", post(handlers::duplicate_todo)) // (POST) calls handlers::duplicate_todo
+        .route("/todos/{id}/move", post(handlers::move_todo)) // (POST) calls handlers::move_todo
+        .route("/todos/{id}/attachments", post(handlers::add_attachment)) // (POST) calls handlers::add_attachment
+        .route("/todos/{id}/attachments/{attachment_id}", delete(handlers::remove_attachment)) // (DELETE) calls handlers::remove_attachment
+        .route("/todos/{id}/archive", post(handlers::archive_todo)) // (POST) calls handlers::archive_todo
+        .route("/todos/{id}/unarchive", post(handlers::unarchive_todo)) // (POST) calls handlers::unarchive_todo
+        .route("/version", get(handlers::get_version)) // (GET) calls handlers::get_version, public like the rest of the API
+        .route("/ws", get(events::ws_handler)) // (GET) upgrades to a WebSocket that receives todo change events
+        .route("/metrics", get(metrics::get_metrics)); // (GET) Prometheus metrics, see metrics.rs
+
+    // the admin-only routes, all of them behind the admin check (see auth.rs)
+    let mut admin_routes = Router::new()
+        .route("/admin/read-only", post(handlers::set_read_only)); // (POST) calls handlers::set_read_only
+
+    // development-only routes aren't registered at all unless ALLOW_DEV_ENDPOINTS=true, so elsewhere they're a 404
+    if state.config.allow_dev_endpoints {
+        admin_routes = admin_routes.route("/admin/reset", post(handlers::reset_database)); // (POST) calls handlers::reset_database
+    }
+
+    let admin_routes = admin_routes.route_layer(from_fn_with_state(state.clone(), auth::require_admin)); // 401/403 for non-admins
+
+    let mut routes = routes
+        .merge(admin_routes)
+        .route_layer(from_fn(middleware::require_json)) // 415 for a write whose body isn't JSON
+        .route_layer(from_fn(middleware::pretty_json)) // indents the JSON responses with ?pretty=true
+        .route_layer(from_fn_with_state(state.clone(), middleware::read_only_guard)) // rejects writes in read-only mode
+        .route_layer(from_fn_with_state(state.clone(), tenant::resolve_tenant)) // picks the tenant's schema, 404 for an unknown one
+        .route_layer(from_fn_with_state(state.clone(), middleware::shutdown_guard)); // rejects new requests while shutting down
+
+    // only added with LOG_BODIES=true, so bodies aren't buffered at all otherwise
+    if state.config.log_bodies {
+        routes = routes.layer(from_fn_with_state(state.clone(), middleware::log_bodies)); // logs JSON bodies, see middleware.rs
+    }
+
+    let routes = routes.with_state(state); // allows handlers to access the database connection pool and config
+
+    with_middleware(routes, request_timeout)
+}
+
+/*
+Wraps the routes in the middleware every request goes through.
+Each .layer() wraps everything added before it, so the last layer listed is the first to see a request:
+      1. SetRequestIdLayer gives the request an x-request-id (a UUID) unless the client already sent one
+      2. PropagateRequestIdLayer copies that id onto the response, so clients can quote it in bug reports
+      3. TraceLayer opens a span carrying the request id, so everything logged while handling the request is tagged with it
+      4. CatchPanicLayer turns a panicking handler into a 500 instead of dropping the connection
+      5. TimeoutLayer cuts off handlers that run too long
+      6. SetResponseHeaderLayer adds Retry-After to every 503, telling clients when to try again after being shed
+         (or rejected during read-only mode) instead of retrying immediately
+*/
+fn with_middleware(routes: Router, request_timeout: Duration) -> Router {
+    routes
+        .layer(SetResponseHeaderLayer::if_not_present(header::RETRY_AFTER, retry_after))
+        .layer(request_timeout_layer(request_timeout)) // applies the request timeout to every route above
+        .layer(CatchPanicLayer::custom(panic_response))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+// a handler that takes longer than `timeout` is dropped and the client gets 408 Request Timeout instead of hanging
+fn request_timeout_layer(timeout: Duration) -> TimeoutLayer {
+    TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, timeout)
+}
+
+// Retry-After for a 503 Service Unavailable, nothing for any other response
+fn retry_after(response: &Response) -> Option<HeaderValue> {
+    (response.status() == StatusCode::SERVICE_UNAVAILABLE).then(|| HeaderValue::from_static(RETRY_AFTER_SECS))
+}
+
+// how many seconds a client is asked to wait before retrying a 503
+const RETRY_AFTER_SECS: &str = "1";
+
+// the span each request is handled in, tagged with its method, uri and request id
+fn request_span(request: &Request<Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!("request", method = %request.method(), uri = %request.uri(), request_id)
+}
+
+// called with the panic payload when a handler panics: log the message (inside the request's span, so with its request id)
+// and answer with a generic 500 rather than leaking the panic details to the client
+fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied()) // panic!("literal") carries a &str, panic!("{}", x) a String
+        .unwrap_or("unknown panic payload");
+    tracing::error!(panic = message, "handler panicked");
+
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "internal server error" }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt; // provides oneshot() to send a single request through the router
+
+    use super::{request_timeout_layer, with_middleware};
+
+    #[tokio::test]
+    async fn slow_handler_times_out_with_408() {
+        // a handler that takes far longer than the timeout allows
+        let app = Router::new()
+            .route("/slow", get(|| async { tokio::time::sleep(Duration::from_secs(5)).await; }))
+            .layer(request_timeout_layer(Duration::from_millis(50)));
+
+        let response = app
+            .oneshot(Request::builder().uri("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    // stands in for a handler with an unexpected bug
+    async fn panicking_handler() -> StatusCode {
+        panic!("boom")
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_returns_500_json() {
+        let app = with_middleware(
+            Router::new().route("/boom", get(panicking_handler)),
+            Duration::from_secs(15),
+        );
+
+        let response = app
+            .oneshot(Request::builder().uri("/boom").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().contains_key("x-request-id"));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], br#"{"error":"internal server error"}"#);
+    }
+}
@@ -1,51 +1,64 @@
-use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use axum::Router;
-use axum::routing::{ delete, get, post };
+use axum_server::Handle;
+use axum_server::tls_rustls::RustlsConfig;
 use diesel::{ PgConnection, r2d2 };
 use diesel::r2d2::ConnectionManager;
 use dotenvy::dotenv;
+use futures::future::{FutureExt, Shared};
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use tokio::signal;
-
-mod models;
-mod handlers;
-mod schema;
+use todo_rs::config::{mask_credentials, redact_password, Config, LogFormat};
+use todo_rs::state::AppState;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt; // provides with() to stack layers on the subscriber
+use tracing_subscriber::util::SubscriberInitExt; // provides init()
+use tracing_subscriber::Layer; // provides boxed()
 
 #[tokio::main] // a procedural macro that marks the main function as asynchronous and it runs on the Tokio runtime
 async fn main() {
     dotenv().ok(); // calls the dotenv() fxn to load environment variables from a .env file into the process environment
 
-    //fetch the DATABASE_URL environment variable, which will contain the database connection string
-    // if DATABASE_URL is not set, it panics with an error message
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    // read all of our settings (the DATABASE_URL connection string, timeouts, ...) from the environment once, up front
+    // if anything required is missing or invalid, list every problem and exit with code 1 instead of panicking
+    let config = Config::from_env().unwrap_or_else(|e| {
+        eprintln!("Cannot start the server, {}", e);
+        eprintln!("Set these in the environment or in a .env file.");
+        std::process::exit(1);
+    });
 
-    // Diesel connection manager for psql and then initializes it with the database URL
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    // print the logs emitted with the tracing macros (e.g. a handler panic, tagged with its request id) to stdout
+    // (and export the spans to an OpenTelemetry collector, if one is configured)
+    let tracer_provider = init_tracing(&config);
 
-    // create a connection pool using r2d2, a thread-safe connection pool manager
-    // set the max number of connections
-    // if the pool creation fails, panic with "Failed to create pool."
-    let pool = r2d2::Pool::builder().max_size(5).build(manager).expect("Failed to create pool.");
+    // create the database connection pool (see build_pool)
+    // if the database can't be reached, log why without the password in DATABASE_URL and exit with code 1 instead of panicking
+    let pool = build_pool(&config).unwrap_or_else(|e| {
+        tracing::error!(
+            database_url = %mask_credentials(&config.database_url),
+            error = %redact_password(&e.to_string(), &config.database_url),
+            "cannot start the server, failed to connect to the database"
+        );
+        std::process::exit(1);
+    });
 
     // wrap the connection pool in an Arc (Atomic Reference Counted) smart pointer to allow safe sharing between threads
-    let db_connection = Arc::new(pool);
-
-    let app = Router::new() // creates an Axum router
-        // define API routes for handling todos using HTTP methods (GET, POST, DELETE)
-        .route("/todos", post(handlers::create_todo)) // (POST) calls handlers::create_todo
-        .route("/todos", get(handlers::get_todos)) // (GET) calls handlers::get_todos
-        .route("/todos/{id}", get(handlers::get_todo)) // (GET) calls handlers::get_todo
-        .route("/todos/{id}", post(handlers::update_todo)) // (POST) calls handlers::update_todo
-        .route("/todos/{id}", delete(handlers::delete_todo)) // (DELETE) calls handlers::delete_todo
-        .with_state(db_connection.clone()); // allows handlers to access the database connection pool
-
-    // create a TCP listener bound to port 8080, listening on our local IP addr
-    // ensure if binding fails, the application panics
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await.unwrap();
+    // and bundle it with the config (and the todo cache, if enabled) into the state every handler receives
+    let state = AppState::new(Arc::new(pool), config);
+
+    // start the background tasks that create the next instance of completed recurring todos (see scheduler.rs)
+    // and remove deleted todos for good once they're old enough (see purge.rs)
+    todo_rs::scheduler::spawn(state.clone());
+    todo_rs::purge::spawn(state.clone());
 
-    // start the Axum server with the given listener and router
-    // ensure the server shuts down gracefully when a shutdown signal is received
-    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown_signal());
+    // build the router with all of our routes and middleware (see todo_rs::app in lib.rs)
+    // and get ready to drain it once a shutdown signal arrives (see shutdown_signal)
+    let app = todo_rs::app(state.clone());
+    let shutdown = shutdown_signal(state.clone()).boxed().shared();
+    let grace = state.config.shutdown_grace;
+    let addr = state.config.listen_addr;
 
     // spawn an async task that simply prints "Server is running"
     // task will exit immediately since it does not contain an infinite loop or delay
@@ -53,15 +66,143 @@ async fn main() {
         println!("Server is running");
     });
 
-    // await the server future
+    // serve over HTTPS when TLS_CERT_PATH and TLS_KEY_PATH are set, otherwise over plain HTTP
+    let result = match &state.config.tls {
+        Some(tls) => serve_https(app, addr, &tls.cert_path, &tls.key_path, shutdown, grace).await,
+        None => serve_http(app, addr, shutdown, grace).await,
+    };
+
     // if an error occurs while running the server, it prints an error message
-    if let Err(e) = server.await {
+    if let Err(e) = result {
         eprintln!("Server error: {}", e);
     }
+
+    // once the last requests are done, send the spans still waiting in the exporter's batch before we exit
+    // (a blocking call, so off the async threads); metrics need nothing, they're only ever pulled from /metrics
+    if let Some(tracer_provider) = tracer_provider {
+        flush_traces(tracer_provider).await;
+    }
+}
+
+// the connection pool every handler checks its database connections out of
+fn build_pool(config: &Config) -> Result<r2d2::Pool<ConnectionManager<PgConnection>>, r2d2::PoolError> {
+    // Diesel connection manager for psql and then initializes it with the database URL
+    let manager = ConnectionManager::<PgConnection>::new(&config.database_url);
+
+    // create a connection pool using r2d2, a thread-safe connection pool manager
+    // set the max number of connections (DB_POOL_SIZE)
+    // wait at most a third of the request timeout for a free connection, so a saturated pool surfaces as 503 before the request itself times out
+    // building it opens the first connections, so it fails if the database can't be reached (after that same wait)
+    r2d2::Pool::builder()
+        .max_size(config.pool_size)
+        .connection_timeout(config.request_timeout / 3)
+        .build(manager)
+}
+
+// how long the exporter gets to send the last spans on the way out, so a collector that's down can't hold up a deploy
+const TRACE_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// flush the spans the exporter still holds and shut it down, logging how that went
+// (a batch that failed to send earlier was already logged by the exporter itself when it happened)
+async fn flush_traces(tracer_provider: SdkTracerProvider) {
+    let flushed = tokio::task::spawn_blocking(move || tracer_provider.shutdown_with_timeout(TRACE_FLUSH_TIMEOUT)).await;
+    match flushed {
+        Ok(Ok(())) => tracing::info!("flushed the remaining traces and shut down the exporter"),
+        Ok(Err(e)) => tracing::warn!(error = %e, "failed to flush the remaining traces, some may be lost"),
+        Err(e) => tracing::warn!(error = %e, "flushing the remaining traces panicked, some may be lost"),
+    }
+}
+
+/*
+Set up the tracing subscriber that writes our logs, in the format picked by LOG_FORMAT:
+      - pretty: multi-line, colored output that's easy to read in a terminal
+      - json: one JSON object per line with the timestamp, level, target and message, for a log collector to parse
+Both include the fields of the span a line was logged in, so everything logged while handling a request carries its request_id
+(in JSON under "span", e.g. {"span": {"name": "request", "request_id": "...", ...}, ...}).
+Lines are logged from info level up, or from debug level with LOG_BODIES=true, since that's the level bodies are logged at.
+With OTEL_EXPORTER_OTLP_ENDPOINT set, the spans are also exported to that OpenTelemetry collector (see telemetry.rs),
+and the tracer provider doing it is returned, for main to flush on the way out.
+*/
+fn init_tracing(config: &Config) -> Option<SdkTracerProvider> {
+    let level = if config.log_bodies { LevelFilter::DEBUG } else { LevelFilter::INFO };
+    let logs = match config.log_format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true) // the innermost span's fields, where the request id lives
+            .with_span_list(false) // the full list of parent spans would just repeat it
+            .boxed(),
+    };
+
+    // a broken exporter setup shouldn't keep the API from starting, it just runs without exported traces
+    let tracer_provider = config.otel_endpoint.as_ref().and_then(|_| {
+        todo_rs::telemetry::tracer_provider()
+            .inspect_err(|e| eprintln!("Failed to set up the OpenTelemetry exporter, traces won't be exported: {}", e))
+            .ok()
+    });
+    let traces = tracer_provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(todo_rs::telemetry::tracer(provider)));
+
+    tracing_subscriber::registry().with(level).with(traces).with(logs).init();
+    tracer_provider
+}
+
+// the shutdown signal, shared so both the server and the grace period timer can wait for it
+type ShutdownSignal = Shared<futures::future::BoxFuture<'static, ()>>;
+
+/*
+Plain HTTP: axum's own server, shutting down gracefully when a shutdown signal is received.
+It stops accepting connections right away and waits for in-flight requests to finish, but axum::serve would wait forever
+(e.g. for a WebSocket client that never disconnects), so we give up on them SHUTDOWN_GRACE_SECS (default 30) after the signal.
+*/
+async fn serve_http(app: Router, addr: SocketAddr, shutdown: ShutdownSignal, grace: Duration) -> std::io::Result<()> {
+    // create a TCP listener bound to HOST:PORT (127.0.0.1:8080 by default)
+    // ensure if binding fails, the application panics
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.clone());
+    tokio::select! {
+        result = server => result,
+        _ = async { shutdown.await; tokio::time::sleep(grace).await } => {
+            eprintln!("in-flight requests didn't finish within {:?} of the shutdown signal, stopping anyway", grace);
+            Ok(())
+        }
+    }
 }
 
 /*
-Line 18-31: Set up the database connection pool
+HTTPS: axum's own server doesn't do TLS, so we hand the router to axum-server with a rustls config loaded from PEM files.
+If the cert or key can't be read or parsed, we refuse to start rather than silently serving plain HTTP.
+
+Graceful shutdown works the same as for plain HTTP, but is wired up differently: axum-server has no with_graceful_shutdown,
+so a background task waits on the same shutdown signal and then tells the server's Handle to shut down.
+The server stops accepting new connections right away and waits up to SHUTDOWN_GRACE_SECS for in-flight requests to finish.
+*/
+async fn serve_https(app: Router, addr: SocketAddr, cert_path: &str, key_path: &str, shutdown: ShutdownSignal, grace: Duration) -> std::io::Result<()> {
+    let tls_config = match RustlsConfig::from_pem_file(cert_path, key_path).await {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            eprintln!("Failed to load TLS certificate ({}) or key ({}): {}", cert_path, key_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let handle = Handle::new(); // used to trigger the graceful shutdown from outside the server
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        shutdown_handle.graceful_shutdown(Some(grace)); // after that, the remaining connections are closed
+    });
+
+    axum_server::bind_rustls(addr, tls_config)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+}
+
+/*
+Line 24-49: Load the config, set up logging and the database connection pool, and build the shared app state
 - a pool is a managed set of db connections that can be reused instead of opening a new connection each time one is needed
       pros:
             - efficiency
@@ -85,15 +226,25 @@ Line 18-31: Set up the database connection pool
 
 r2d2 (short for "Reliable Redis Database Pool" but now generalized) is a connection pool manager for Rust. It helps manage a set of database connections efficiently, preventing the overhead of opening and closing connections frequently.
 
-Line 33-40: Define the routes for our API
+Line 51-54: Start the recurring todo scheduler and the deleted todo purge in the background
+
+Line 56-61: Build the router with the routes for our API (defined in lib.rs, so the tests can build the same app), and prepare for a graceful shutdown
+
+Line 63-84: Log application startup, serve over HTTPS or plain HTTP depending on the TLS settings, and flush the exported traces once the server stops
+
+Line 87-100: Build the connection pool, which fails (rather than panics) when the database can't be reached; main logs
+why with the DATABASE_URL password masked (see mask_credentials in config.rs), so it never ends up in a log aggregator
+
+Line 102-114: Flush the exported traces with a timeout, logging whether it worked
 
-Line 42-48: Set up the server address
+Line 116-149: Set up logging in the pretty or JSON format, and the OpenTelemetry trace export
 
-Line 50-61: Log application startup or failure
+Line 151-202: Run the HTTP or HTTPS server on HOST:PORT, draining it on shutdown
 */
 
 // This function waits for a termination signal (Ctrl+C) and then starts a graceful shutdown of the application.
-async fn shutdown_signal() {
+// From then on the app is draining: requests that still arrive get 503 (see shutdown_guard in middleware.rs).
+async fn shutdown_signal(state: AppState) {
     // create an async block that listens for Ctrl+C
     let ctrl_c = async {
         // wait until the users presses Ctrl+C
@@ -119,5 +270,6 @@ async fn shutdown_signal() {
         _ = terminate => {}, // if SIGTEM is received (on UNIX)
     }
 
+    state.start_shutdown(); // before the server is told to stop, so nothing slips in between
     println!("signal received, starting graceful shutdown"); // prints a message when a termination signal is received
 }
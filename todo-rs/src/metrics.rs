@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU64, Ordering}; // counters every request can bump without a lock
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use crate::state::AppState;
+
+// Metrics - counters kept for the lifetime of the process, shared through AppState
+#[derive(Default)]
+pub struct Metrics {
+    requests_shed: AtomicU64, // requests answered with 503 because the database pool was saturated, see run_db
+}
+
+impl Metrics {
+    pub fn record_shed_request(&self) {
+        self.requests_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn requests_shed(&self) -> u64 {
+        self.requests_shed.load(Ordering::Relaxed)
+    }
+}
+
+// GET metrics
+// The counters above plus the database pool's current usage, in the Prometheus text format so a scraper can collect them.
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let pool = state.pool.state();
+
+    let body = format!(
+        "# HELP todo_db_pool_connections Database connections currently open.\n\
+         # TYPE todo_db_pool_connections gauge\n\
+         todo_db_pool_connections {}\n\
+         # HELP todo_db_pool_idle_connections Open database connections not in use.\n\
+         # TYPE todo_db_pool_idle_connections gauge\n\
+         todo_db_pool_idle_connections {}\n\
+         # HELP todo_db_pool_max_connections The most database connections the pool opens.\n\
+         # TYPE todo_db_pool_max_connections gauge\n\
+         todo_db_pool_max_connections {}\n\
+         # HELP todo_requests_shed_total Requests rejected with 503 because every database connection was busy.\n\
+         # TYPE todo_requests_shed_total counter\n\
+         todo_requests_shed_total {}\n",
+        pool.connections,
+        pool.idle_connections,
+        state.pool.max_size(),
+        state.metrics.requests_shed(),
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -0,0 +1,138 @@
+use axum::body::{to_bytes, Body, Bytes, HttpBody};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use crate::extract::{is_json, is_multipart, pretty_scope, unsupported_media_type};
+use crate::models::Pretty;
+use crate::state::AppState;
+
+/*
+Read-only mode, for maintenance windows: while it's on, reads keep working but every write (any method other than
+GET, HEAD or OPTIONS) is answered with 503 {"error": "read-only mode"} before it reaches a handler.
+It starts as READ_ONLY and can be flipped at runtime with POST /admin/read-only, which is let through so it can be turned off again.
+*/
+pub async fn read_only_guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let is_write = !matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if is_write && state.is_read_only() && request.uri().path() != "/admin/read-only" {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": "read-only mode" }))).into_response();
+    }
+
+    next.run(request).await
+}
+
+/*
+Draining, for rolling deploys behind a load balancer: once a shutdown signal arrives (see main.rs), the server stops
+accepting connections and lets the requests it's already handling finish. A request that still reaches us in the meantime,
+e.g. on a kept-alive connection, is answered with 503 {"error": "shutting down"} (and Retry-After, like every 503) instead
+of being started, with Connection: close so the client opens its next connection to another instance.
+*/
+pub async fn shutdown_guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.is_shutting_down() {
+        let headers = [(header::CONNECTION, "close")];
+        return (StatusCode::SERVICE_UNAVAILABLE, headers, Json(json!({ "error": "shutting down" }))).into_response();
+    }
+
+    next.run(request).await
+}
+
+/*
+Write requests have to say their body is JSON: a POST, PUT or PATCH that carries a body with any other Content-Type
+(or none) is answered with 415 Unsupported Media Type before it reaches a handler, in the same shape as the Json
+extractor's errors, rather than whatever the handler would have made of it. application/json; charset=utf-8 and
+JSON-based types like application/merge-patch+json are fine (see is_json).
+Writes without a body (e.g. POST /todos/{id}/archive) need no Content-Type, and a multipart/form-data upload is let
+through whatever route it's sent to, since that's what POST /todos/import-file takes instead of JSON.
+This goes by the Content-Type rather than the path, so renaming or nesting the upload route doesn't need a change here;
+an upload sent to a JSON route still gets the same 415 from the Json extractor.
+*/
+pub async fn require_json(request: Request, next: Next) -> Response {
+    let has_body_method = matches!(*request.method(), Method::POST | Method::PUT | Method::PATCH);
+    let has_body = request.body().size_hint().exact() != Some(0); // known to be empty, e.g. Content-Length: 0 or none at all
+    if has_body_method && has_body && !is_json(request.headers()) && !is_multipart(request.headers()) {
+        return unsupported_media_type();
+    }
+
+    next.run(request).await
+}
+
+/*
+Indented JSON, for reading responses in a browser while debugging: with ?pretty=true or an X-Pretty: true header,
+every JSON response the handlers send (see Json in extract.rs) is written with serde_json::to_string_pretty,
+otherwise it stays compact. Only how the JSON is laid out changes, not its Content-Type or anything else about the response.
+The NDJSON and CSV lists and the export are left as they are: one line per todo, and byte-for-byte the same on every
+download (which resuming one with a Range relies on).
+*/
+pub async fn pretty_json(request: Request, next: Next) -> Response {
+    let from_query = match Query::<Pretty>::try_from_uri(request.uri()) {
+        Ok(Query(Pretty { pretty })) => pretty,
+        Err(rejection) => return rejection.into_response(), // 400 for e.g. ?pretty=maybe
+    };
+    let from_header = request.headers().get("x-pretty").is_some_and(|value| value == "true");
+
+    pretty_scope(from_query || from_header, next.run(request)).await
+}
+
+/*
+Body logging, for reproducing a client's bug: with LOG_BODIES=true (which also lowers the log level to debug, see main.rs)
+every request and response with a JSON body is logged at debug level, inside the request's span so with its request id.
+      - only JSON bodies are logged; anything else (CSV, NDJSON streams, file uploads) just passes through untouched
+      - each body is cut off after LOG_BODY_MAX_BYTES, with its full length noted
+      - the request headers are logged too, but never the credentials in Authorization, Proxy-Authorization or Cookie
+A body has to be read in full to be logged, so it's buffered and then handed on as a fresh body with the same bytes,
+which the handler (or the client) reads as if nothing had happened. A request body is buffered up to the same 2 MB
+limit axum's extractors enforce, one that's larger is rejected with 413 as the extractor would.
+*/
+pub async fn log_bodies(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let max = state.config.log_body_max_bytes;
+
+    let (parts, body) = request.into_parts();
+    let body = if is_json(&parts.headers) {
+        let Ok(bytes) = to_bytes(body, REQUEST_BUFFER_LIMIT).await else {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response(); // (or the client went away mid-body)
+        };
+        tracing::debug!(method = %parts.method, uri = %parts.uri, headers = ?loggable_headers(&parts.headers), body = %truncated(&bytes, max), "request body");
+        Body::from(bytes)
+    } else {
+        body
+    };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if !is_json(response.headers()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response(); // our JSON bodies are already in memory, this doesn't happen
+    };
+    tracing::debug!(status = %parts.status, body = %truncated(&bytes, max), "response body");
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// the most of a request body log_bodies reads into memory, the same as axum's default body limit
+const REQUEST_BUFFER_LIMIT: usize = 2 * 1024 * 1024;
+
+// headers whose values are credentials, logged as [redacted]
+const REDACTED_HEADERS: &[header::HeaderName] = &[header::AUTHORIZATION, header::PROXY_AUTHORIZATION, header::COOKIE];
+
+// the headers as name: value pairs, with the credentials blanked out
+fn loggable_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(name) { "[redacted]".into() } else { String::from_utf8_lossy(value.as_bytes()).into_owned() };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+// the body as text, cut off after `max` bytes
+fn truncated(bytes: &Bytes, max: usize) -> String {
+    if bytes.len() <= max {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    format!("{}... ({} bytes in total)", String::from_utf8_lossy(&bytes[..max]), bytes.len())
+}
@@ -1,13 +1,47 @@
+use chrono::{DateTime, Duration, Months, Utc}; // timestamps, stored as TIMESTAMPTZ and serialized as RFC 3339 strings
+use diesel::deserialize::{self, FromSql, FromSqlRow}; // reading a Recurrence back from its TEXT column
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*; // brings in Diesel traits and functions, allowing interaction with the db
-use serde::{Deserialize, Serialize}; // allows structs to be converted to/from JSON to API responses
+use diesel::serialize::{self, Output, ToSql}; // writing a Recurrence to its TEXT column
+use diesel::expression::AsExpression; // lets a Recurrence be bound as a query parameter
+use diesel::sql_types::Text;
+use std::str::FromStr; // parses the default sort from the config
+use serde::de::IntoDeserializer; // lets serde parse a plain string into an enum
+use serde::{de, Deserialize, Deserializer, Serialize}; // allows structs to be converted to/from JSON to API responses
+
+/*
+TodoId - the type of a todo's id: a serial integer (1, 2, 3, ...) by default, or with the uuid-ids feature a random UUID
+generated by Postgres' gen_random_uuid(), which doesn't give away how many todos there are and can't be guessed.
+UUID ids need the database converted first, with the migration in migrations_uuid/ (after the ones in migrations/):
+      diesel migration run --migration-dir migrations_uuid
+Either way an id in a path that doesn't parse (e.g. /todos/abc), or an integer one that can't exist (e.g. /todos/0), is
+rejected with 400 by the IdPath extractor, see extract.rs.
+*/
+#[cfg(not(feature = "uuid-ids"))]
+pub type TodoId = i32;
+#[cfg(feature = "uuid-ids")]
+pub type TodoId = uuid::Uuid;
 
 // Queryable - enables Diesel to fetch db rows and map them into this struct
 // Serialize - allows the struct to be serialized into JSON for API responses
-#[derive(Queryable,Serialize)] // applies the two derive macros to the struct that precedes it
+// Clone - lets the cache hand out copies of the todos it holds
+#[derive(Queryable,Serialize,Clone)] // applies the derive macros to the struct that precedes it
 pub struct Todo {
-    pub id: i32, // unique identifier of the todo item
+    pub id: TodoId, // unique identifier of the todo item
     pub title: String, // title of todo item
     pub content: String, // content/description of the todo
+    pub completed: bool, // whether the todo has been marked as done
+    pub version: i32, // starts at 1 and goes up by one on every change to the todo (not on moves), also backs its ETag
+    pub position: i32, // where the todo sits in the list, 0 is the top
+    pub created_at: DateTime<Utc>, // when the todo was created, set by the database
+    pub due_date: Option<DateTime<Utc>>, // when the todo should be done by, if it has a deadline
+    pub recurrence: Recurrence, // how often the todo repeats, see scheduler.rs
+    pub recurred_from: Option<TodoId>, // the previous instance of a recurring todo, None for the first one
+    pub updated_at: DateTime<Utc>, // when the todo last changed, kept up to date by a database trigger
+    pub archived: bool, // moved out of the active list, but still there and editable (unlike a deleted todo)
+    #[serde(skip_serializing)] // a deleted todo is never returned, only its id in GET /todos/changes
+    pub deleted_at: Option<DateTime<Utc>>, // when the todo was deleted, see live() in handlers.rs
+    pub external_id: Option<String>, // its id in the system it's synced from, see upsert_todo in handlers.rs
 }
 
 // Insertable - allows this struct to be used for inserting new rows into the db
@@ -15,15 +49,341 @@ pub struct Todo {
 #[derive(Insertable,Deserialize)]
 #[diesel(table_name = crate::schema::todos)] // specifies that this struct maps to the todos table in the db schema
 pub struct NewTodo { // defines NewTodo, which omits id since the database assigns it automatically
+    #[serde(deserialize_with = "title")]
     pub title: String,
+    #[serde(deserialize_with = "content")]
     pub content: String,
+    #[serde(default)]
+    pub due_date: Option<DateTime<Utc>>, // optional deadline
+    #[serde(default)]
+    pub recurrence: Recurrence, // defaults to none, a one-off todo
 }
 
 // AsChangeSet - allows Diesel to use this struct to update an existing database record
 // Deserialize - enables JSON conversion when updating a todo via an API
 #[derive(AsChangeset,Deserialize)]
 #[diesel(table_name = crate::schema::todos)] // specifies that this struct corresponds to the todo table
-pub struct UpdateTodo { // defines UpdateTodo which allows updating only specific fields (title and content)
+pub struct UpdateTodo { // defines UpdateTodo which allows updating only specific fields (title, content, completed, due_date and recurrence)
+    #[serde(deserialize_with = "title")]
+    pub title: String,
+    #[serde(deserialize_with = "content")]
+    pub content: String,
+    pub completed: Option<bool>, // None leaves the completed flag untouched
+    pub due_date: Option<DateTime<Utc>>, // None leaves the deadline untouched
+    pub recurrence: Option<Recurrence>, // None leaves the recurrence untouched
+}
+
+/*
+Recurrence - how often a todo repeats: "none", "daily", "weekly" or "monthly" in JSON and in the TEXT column.
+Once a recurring todo is completed, the scheduler creates its next instance with the due date moved on by one period.
+Any other value in a request body is rejected by serde with 422 before it reaches the database.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "lowercase")]
+#[diesel(sql_type = Text)]
+pub enum Recurrence {
+    #[default]
+    None,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Recurrence {
+    // the same lowercase name serde uses, as stored in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Recurrence::None => "none",
+            Recurrence::Daily => "daily",
+            Recurrence::Weekly => "weekly",
+            Recurrence::Monthly => "monthly",
+        }
+    }
+
+    // the due date one period after `due`, None for a todo that doesn't repeat
+    // monthly keeps the day of the month where it can and clamps it otherwise (Jan 31 -> Feb 28)
+    pub fn next_after(self, due: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Recurrence::None => None,
+            Recurrence::Daily => Some(due + Duration::days(1)),
+            Recurrence::Weekly => Some(due + Duration::weeks(1)),
+            Recurrence::Monthly => due.checked_add_months(Months::new(1)),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for Recurrence {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <str as ToSql<Text, Pg>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for Recurrence {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Recurrence> {
+        match <String as FromSql<Text, Pg>>::from_sql(value)?.as_str() {
+            "none" => Ok(Recurrence::None),
+            "daily" => Ok(Recurrence::Daily),
+            "weekly" => Ok(Recurrence::Weekly),
+            "monthly" => Ok(Recurrence::Monthly),
+            other => Err(format!("unknown recurrence {:?}", other).into()),
+        }
+    }
+}
+
+/*
+Titles and contents are cleaned up as they're deserialized, before they get anywhere near the database:
+a title is trimmed and every run of whitespace inside it (double spaces, tabs, a pasted newline) becomes one space,
+and a title that's empty after that is rejected (422 for a request body, a failed record for an import).
+Content is a free-form description, so only its trailing whitespace is trimmed and its formatting is kept.
+*/
+fn title<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let title = String::deserialize(deserializer)?.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        return Err(de::Error::custom("title must not be empty"));
+    }
+    Ok(title)
+}
+
+fn content<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let mut content = String::deserialize(deserializer)?;
+    content.truncate(content.trim_end().len());
+    Ok(content)
+}
+
+// Deserialize - the body of POST /todos/{id}/move
+#[derive(Deserialize)]
+pub struct MoveTodo {
+    pub position: i32, // the new position, positions past the end of the list move the todo to the end
+}
+
+// Queryable - a link or file attached to a todo, see the attachments table
+// Serialize - embedded in GET /todos/{id}?include=attachments and returned when one is added
+#[derive(Queryable, Serialize)]
+pub struct Attachment {
+    pub id: i32,
+    pub todo_id: TodoId, // the todo it belongs to, deleting the todo deletes its attachments
+    pub url: String, // an http:// or https:// URL
+    pub label: String, // what to show instead of the URL, may be empty
+    pub created_at: DateTime<Utc>,
+}
+
+// Deserialize - the body of POST /todos/{id}/attachments; Insertable together with the todo_id from the path
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::attachments)]
+pub struct NewAttachment {
+    pub url: String,
+    #[serde(default)]
+    pub label: String,
+}
+
+// Deserialize - parses the ?include= query param of GET /todos/{id}, e.g. ?include=attachments
+#[derive(Deserialize)]
+pub struct TodoInclude {
+    pub include: Option<String>, // a comma-separated list of related data to embed, only "attachments" for now
+}
+
+// Deserialize - the body of the batch endpoints, e.g. POST /todos/complete with {"ids": [1, 2, 3]}
+#[derive(Deserialize)]
+pub struct TodoIds {
+    pub ids: Vec<TodoId>, // the todos to act on, ids that don't exist are skipped
+}
+
+// Deserialize - parses the query string (e.g. ?completed=true) into this struct
+// Clone - the NDJSON stream reuses the filter for every batch
+#[derive(Deserialize, Clone)]
+pub struct TodoFilter { // defines the optional filters shared by the list and count endpoints
+    pub completed: Option<bool>, // only match todos with this completed status when present
+    #[serde(default, deserialize_with = "rfc3339")]
+    pub created_after: Option<DateTime<Utc>>, // only match todos created at or after this time
+    #[serde(default, deserialize_with = "rfc3339")]
+    pub created_before: Option<DateTime<Utc>>, // only match todos created at or before this time
+    #[serde(default)]
+    pub archived: bool, // false (the default) matches the active todos, true the archived ones instead
+    #[serde(default, deserialize_with = "comma_separated_ids")]
+    pub ids: Option<Vec<TodoId>>, // only match todos with one of these ids, e.g. ?ids=1,2,3
+}
+
+// Deserialize - parses the query string of GET /todos/changes, e.g. ?since=2025-05-28T09:15:06.123456Z
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    #[serde(default, deserialize_with = "rfc3339")]
+    pub since: Option<DateTime<Utc>>, // only changes after this time, every todo when absent (a first, full sync)
+    pub after_id: Option<TodoId>, // for the next page: changes at exactly `since` with a higher id are included too
+}
+
+// parses an RFC 3339 timestamp (e.g. 2025-04-01T09:00:00Z or 2025-04-01T11:00:00+02:00) from the query string
+// a bad value is rejected with a 400 whose message quotes it, rather than being silently ignored
+fn rfc3339<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    DateTime::parse_from_rfc3339(&value)
+        .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+        .map_err(|_| de::Error::custom(format!("invalid timestamp {:?}, expected RFC 3339 like 2025-04-01T09:00:00Z", value)))
+}
+
+// parses a comma-separated list of ids (e.g. 1,2,3) from the query string, rejecting it with a 400 if one of them doesn't parse
+fn comma_separated_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<TodoId>>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|todo_id| !todo_id.is_empty()) // e.g. a trailing comma
+        .map(|todo_id| todo_id.parse::<TodoId>().map_err(|_| de::Error::custom(format!("invalid id {:?} in ids", todo_id))))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+// Deserialize - the body of POST /todos/import, the same shape GET /todos/export produces
+// the records are kept as raw JSON so each one can be validated (and reported on) separately
+#[derive(Deserialize)]
+pub struct ImportDocument {
+    pub todos: Vec<serde_json::Value>,
+}
+
+// Insertable - one validated record of an import; id, position and version are assigned fresh on insert
+// fields left out of the record (None) get the column's default, so an export's created_at is kept but isn't required
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::todos)]
+pub struct ImportTodo {
+    #[serde(deserialize_with = "title")]
     pub title: String,
+    #[serde(deserialize_with = "content")]
     pub content: String,
-}
\ No newline at end of file
+    pub completed: Option<bool>,
+    pub archived: Option<bool>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub due_date: Option<DateTime<Utc>>,
+    pub recurrence: Option<Recurrence>,
+}
+
+// Deserialize - parses the ?mode= query param of POST /todos/import
+#[derive(Deserialize)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+// merge (the default) adds the imported todos after the existing ones, replace deletes the existing ones first
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Merge,
+    Replace,
+}
+
+// Deserialize - the body of POST /admin/read-only, e.g. {"read_only": true}
+#[derive(Deserialize)]
+pub struct ReadOnlyToggle {
+    pub read_only: bool,
+}
+
+// Deserialize - parses the ?q= query param of GET /todos/search
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String, // a Postgres tsquery, e.g. milk, milk & bread, milk | !oat, or bre:* (prefix match)
+}
+
+// Serialize - one GET /todos/search result: the todo's usual fields, plus how relevant it is and where it matched
+#[derive(Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)] // the todo's fields sit next to rank and highlight rather than under a "todo" key
+    pub todo: Todo,
+    pub rank: f32, // higher means a better match, results are sorted by it
+    pub highlight: String, // an excerpt of the content with the matching words wrapped in <b></b>
+}
+
+// Deserialize - parses the ?format= query param that picks how the todo list is represented
+#[derive(Deserialize)]
+pub struct ListFormat {
+    pub format: Option<String>, // "json" (default) or "ndjson" for a streamed, one-todo-per-line export
+    pub fields: Option<String>, // a comma-separated list of the fields to include, e.g. ?fields=id,title, see TODO_FIELDS
+}
+
+// Deserialize - parses the ?sort=, ?order=, ?limit=, ?offset= and ?envelope= query params of GET /todos, e.g. ?sort=created_at&order=desc&limit=20&offset=40
+#[derive(Deserialize)]
+pub struct ListPage {
+    pub sort: Option<TodoSort>, // DEFAULT_SORT when absent
+    pub order: Option<SortOrder>, // DEFAULT_ORDER when absent
+    pub limit: Option<i64>, // how many todos to return, DEFAULT_PAGE_SIZE when absent and at most MAX_PAGE_SIZE
+    #[serde(default)]
+    pub offset: i64, // how many to skip first
+    #[serde(default)]
+    pub envelope: bool, // whether the page is wrapped as {"data": [...], "page": {...}} rather than a bare array
+}
+
+// Deserialize - parses the ?limit= query param of GET /todos/board
+#[derive(Deserialize)]
+pub struct BoardQuery {
+    pub limit: Option<i64>, // how many todos each column holds at most, like ?limit= for GET /todos
+}
+
+// what the todo list is sorted by, the user's own order (position) unless ?sort= says otherwise
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoSort {
+    #[default]
+    Position,
+    CreatedAt,
+    UpdatedAt,
+    DueDate, // todos without a due date come last either way
+    Title,
+    Version, // how many times a todo was changed, the most-edited ones come first with ?order=desc
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// parses DEFAULT_SORT and DEFAULT_ORDER, which take the same values as ?sort= and ?order=
+impl FromStr for TodoSort {
+    type Err = de::value::Error;
+
+    fn from_str(value: &str) -> Result<TodoSort, de::value::Error> {
+        TodoSort::deserialize(value.into_deserializer())
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = de::value::Error;
+
+    fn from_str(value: &str) -> Result<SortOrder, de::value::Error> {
+        SortOrder::deserialize(value.into_deserializer())
+    }
+}
+
+// the fields a serialized Todo has, in order; ?fields= may pick any of them
+pub const TODO_FIELDS: &[&str] = &[
+    "id", "title", "content", "completed", "version", "position", "created_at", "due_date", "recurrence", "recurred_from", "updated_at",
+    "archived", "external_id",
+];
+
+// Deserialize - parses the ?confirm= query param that DELETE /todos needs before it wipes every todo
+#[derive(Deserialize)]
+pub struct ClearConfirm {
+    pub confirm: Option<bool>,
+}
+
+// Deserialize - parses the ?pretty=true query param any JSON response can be indented with, see pretty_json in middleware.rs
+#[derive(Deserialize)]
+pub struct Pretty {
+    #[serde(default)]
+    pub pretty: bool,
+}
+
+// Deserialize - parses the ?partial=true query param of the bulk endpoints, which reports on every item instead of all-or-nothing
+#[derive(Deserialize)]
+pub struct Partial {
+    #[serde(default)]
+    pub partial: bool,
+}
+
+// Deserialize - parses the ?dry_run=true query param of the delete endpoints, which reports what would be deleted without deleting it
+#[derive(Deserialize)]
+pub struct DryRun {
+    #[serde(default)]
+    pub dry_run: bool,
+}
@@ -0,0 +1,79 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool};
+use tokio::time::MissedTickBehavior; // what the interval does when a run takes longer than the interval
+use crate::handlers::run_db;
+use crate::schema::todos;
+use crate::state::AppState;
+use crate::tenant;
+
+/*
+The purge job - a background task that removes deleted todos for good.
+Deleting a todo only marks it deleted (see live() in handlers.rs), so GET /todos/changes can report it to sync clients.
+Every PURGE_INTERVAL_SECS (default 3600) the rows deleted more than PURGE_AFTER_DAYS ago (default 30) are hard-deleted,
+their attachments with them. A client that hasn't synced for longer than that won't hear about those deletes, and
+should start over with a full sync.
+
+Every server instance runs the job, so each run takes a Postgres advisory lock first and skips the run if another
+instance holds it. It's a transaction-level lock (pg_try_advisory_xact_lock), released when the run's transaction ends,
+so a crashed run can never leave it held on a pooled connection.
+*/
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.config.purge_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if state.is_read_only() {
+                continue; // no writes during maintenance
+            }
+            match run_once(&state).await {
+                Ok(Some(0)) => {}
+                Ok(Some(purged)) => tracing::info!(purged, "purged deleted todos"),
+                Ok(None) => tracing::debug!("another instance is purging deleted todos, skipping this run"),
+                Err(status) => tracing::error!(%status, "deleted todo purge failed"),
+            }
+        }
+    });
+}
+
+// the advisory lock only one purge holds at a time, an arbitrary number no other lock in the database uses
+pub const PURGE_LOCK_KEY: i64 = 0x746f_646f_7075_7267; // "todopurg"
+
+// one purge run: how many rows were purged, or None when another instance holds the lock
+// with MULTI_TENANT=true every tenant's schema is purged in turn
+pub async fn run_once(state: &AppState) -> Result<Option<usize>, StatusCode> {
+    if !state.config.multi_tenant {
+        return run_once_for_tenant(state).await;
+    }
+
+    let tenants = run_db(state, |conn| tenant::all_tenants(conn).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)).await?;
+    let mut purged = 0;
+    for tenant in tenants {
+        match tenant::scope(Some(tenant), run_once_for_tenant(state)).await? {
+            Some(count) => purged += count,
+            None => return Ok(None), // another instance is on it, it will get to the rest too
+        }
+    }
+    Ok(Some(purged))
+}
+
+async fn run_once_for_tenant(state: &AppState) -> Result<Option<usize>, StatusCode> {
+    let cutoff = Utc::now() - state.config.purge_after;
+    run_db(state, move |conn| purge_deleted(conn, cutoff).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)).await
+}
+
+fn purge_deleted(conn: &mut PgConnection, cutoff: DateTime<Utc>) -> QueryResult<Option<usize>> {
+    conn.transaction(|conn| {
+        let locked = diesel::select(diesel::dsl::sql::<Bool>("pg_try_advisory_xact_lock(").bind::<BigInt, _>(PURGE_LOCK_KEY).sql(")"))
+            .get_result::<bool>(conn)?;
+        if !locked {
+            return Ok(None);
+        }
+
+        let purged = diesel::delete(todos::table.filter(todos::deleted_at.lt(cutoff))).execute(conn)?; // attachments cascade
+        Ok(Some(purged))
+    })
+}
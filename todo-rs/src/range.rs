@@ -0,0 +1,78 @@
+use std::ops::Range;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+/*
+Range requests, so a client whose download of a large export broke off can resume it instead of starting over.
+Every response says it supports them (Accept-Ranges: bytes). A request with Range: bytes=<first>-<last>, bytes=<first>-
+or bytes=-<suffix length> gets 206 Partial Content with just those bytes and a Content-Range saying where they are,
+e.g. Content-Range: bytes 1000-1999/52311.
+      - a range that starts past the end of the body is 416 Range Not Satisfiable, with a Content-Range that gives just the length
+      - a Range header we can't parse, or one asking for several ranges at once, is ignored, the whole body is sent (200)
+      - with If-Range: <etag>, the range is only honoured if the body still has that ETag, otherwise it changed since
+        the client's first, interrupted download, so the bytes it has don't belong with ours and it gets the whole body
+The body has to be rendered in full first so we know its length, which is what a response carrying a range has to state.
+That's the case for GET /todos/export and the CSV list (GET /todos with Accept: text/csv), but not for the NDJSON stream,
+which is sent as it's read and so isn't ranged, see stream_todos in handlers.rs.
+There's no response compression in the app: if some is added, it has to leave these responses alone, otherwise it would
+compress the slice and the range would no longer count bytes of what the client actually receives.
+*/
+pub fn ranged_response(headers: &HeaderMap, etag: &str, content_type: &'static str, body: Vec<u8>) -> Response {
+    let length = body.len();
+    let common = [
+        (header::ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+        (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+        (header::ETAG, HeaderValue::from_str(etag).unwrap()), // etags are quoted ASCII
+    ];
+
+    let if_range_matches = headers.get(header::IF_RANGE).is_none_or(|if_range| if_range.as_bytes() == etag.as_bytes());
+    let requested = headers
+        .get(header::RANGE)
+        .filter(|_| if_range_matches)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| byte_range(range, length));
+
+    match requested {
+        None => (StatusCode::OK, common, body).into_response(),
+        Some(None) => {
+            let content_range = format!("bytes */{}", length);
+            (StatusCode::RANGE_NOT_SATISFIABLE, common, [(header::CONTENT_RANGE, content_range)]).into_response()
+        }
+        Some(Some(range)) => {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, length);
+            let part = Body::from(body[range].to_vec());
+            (StatusCode::PARTIAL_CONTENT, common, [(header::CONTENT_RANGE, content_range)], part).into_response()
+        }
+    }
+}
+
+// the bytes a Range header asks for out of a body of `length` bytes: None for a header to ignore,
+// Some(None) when it can't be satisfied, otherwise Some(Some(range)) with the range cut down to the body
+fn byte_range(header: &str, length: usize) -> Option<Option<Range<usize>>> {
+    let spec = header.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None; // several ranges would need a multipart/byteranges body, the whole thing is simpler for everyone
+    }
+    let (first, last) = spec.split_once('-')?;
+
+    let range = match (first.trim(), last.trim()) {
+        // the last `suffix` bytes, e.g. bytes=-500
+        ("", suffix) => {
+            let suffix = suffix.parse::<usize>().ok()?;
+            length.saturating_sub(suffix)..length
+        }
+        // from `first` to the end, e.g. bytes=1000-
+        (first, "") => first.parse::<usize>().ok()?..length,
+        // from `first` to `last`, both included, e.g. bytes=1000-1999
+        (first, last) => {
+            let (first, last) = (first.parse::<usize>().ok()?, last.parse::<usize>().ok()?);
+            if last < first {
+                return None;
+            }
+            first..(last + 1).min(length)
+        }
+    };
+
+    Some((range.start < range.end).then_some(range))
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use crate::handlers::{is_position_collision, query_error, run_db};
+use crate::state::AppState;
+
+/*
+Retrying writes that failed for a reason that has nothing to do with the write itself.
+Some database errors are transient: the same query would most likely succeed if it were simply run again.
+      - a serialization failure, when Postgres aborts one of two concurrent transactions that got in each other's way
+      - a connection that was closed or reset under us, e.g. by a database restart or failover
+      - a position a concurrent write took first, e.g. two moves at once, see is_position_collision
+Rather than handing those to the client as a 500, the write handlers run their queries through with_retry, which retries
+the whole closure (so a transaction inside it starts over) up to DB_RETRIES times (default 3), waiting
+DB_RETRY_BASE_DELAY_MS (default 50) before the first retry and twice as long before each one after it.
+Every retry is logged as a warning. Any other error (a unique violation, a missing row, ...) would only fail again,
+so it's returned straight away, as is the last error once the retries run out (mapped like any failed query, see query_error).
+
+Each attempt checks out its own connection through run_db, so a retry after a lost connection doesn't reuse the broken one
+(r2d2 checks a connection before handing it out and replaces a dead one), and the connection is back in the pool while we wait.
+*/
+pub async fn with_retry<T, F>(state: &AppState, mut query: F) -> Result<T, StatusCode>
+where
+    T: Send + 'static,
+    F: FnMut(&mut PgConnection) -> QueryResult<T> + Send + 'static,
+{
+    let retries = state.config.db_retries;
+    let mut attempt = 0;
+    loop {
+        // the closure goes to the blocking thread and comes back with the result, ready for the next attempt
+        let (returned, result) = run_db(state, move |conn| {
+            let result = query(conn);
+            Ok((query, result))
+        })
+        .await?;
+        query = returned;
+
+        match result {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                let delay = backoff(state.config.db_retry_base_delay, attempt);
+                tracing::warn!(attempt, retries, delay_ms = delay.as_millis() as u64, error = %e, "transient database error, retrying");
+                tokio::time::sleep(delay).await;
+            }
+            result => return result.map_err(query_error),
+        }
+    }
+}
+
+// whether an error is worth retrying, see with_retry
+pub fn is_transient(e: &Error) -> bool {
+    let transient_kind = matches!(
+        e,
+        Error::DatabaseError(
+            DatabaseErrorKind::SerializationFailure | DatabaseErrorKind::ClosedConnection | DatabaseErrorKind::UnableToSendCommand,
+            _
+        )
+    );
+    transient_kind || is_position_collision(e)
+}
+
+// the wait before a retry (the first one is attempt 1): the base delay, doubled for every retry before it
+pub fn backoff(base_delay: Duration, attempt: u32) -> Duration {
+    base_delay * 2u32.pow(attempt - 1)
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use tokio::time::MissedTickBehavior; // what the interval does when a run takes longer than the interval
+use crate::events::TodoEvent;
+use crate::handlers::{check_todo_limit, live, next_position, run_db};
+use crate::models::{NewTodo, Recurrence, Todo};
+use crate::schema::todos;
+use crate::state::AppState;
+use crate::tenant;
+
+/*
+The scheduler - a background task that keeps recurring todos going.
+Every SCHEDULER_INTERVAL_SECS (default 60) it looks for recurring todos that were completed and haven't spawned
+their next instance yet, and creates that instance: same title, content and recurrence, not completed,
+and due one period later (or, if that's already past, the first period end that's still ahead).
+
+Nothing is kept in memory between runs, so a restart picks up exactly where it left off. Each new instance records
+the todo it was spawned from in recurred_from, which is UNIQUE: a todo that already has a next instance is skipped,
+and if two runs ever race for the same todo (two servers, or a restart mid-run) the second insert is simply dropped.
+With multi-tenancy on, each run goes through every tenant's schema (see tenant.rs).
+A list that's full (MAX_TODOS_PER_USER, see check_todo_limit in handlers.rs) gets no new instances, the first run after
+something in it is deleted creates them.
+*/
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.config.scheduler_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay); // don't catch up with a burst of runs after a slow one
+
+        loop {
+            interval.tick().await; // the first tick completes immediately, so a run happens right at startup
+            if state.is_read_only() {
+                continue; // no writes during maintenance, the next run after it catches up
+            }
+            match run_once(&state).await {
+                Ok(0) => {}
+                Ok(created) => tracing::info!(created, "created the next instances of recurring todos"),
+                Err(status) => tracing::error!(%status, "recurring todo scheduler run failed"),
+            }
+        }
+    });
+}
+
+// one scheduler run: create every next instance that is due, and return how many were created
+// with MULTI_TENANT=true that's done in every tenant's schema in turn
+pub async fn run_once(state: &AppState) -> Result<usize, axum::http::StatusCode> {
+    if !state.config.multi_tenant {
+        return run_once_for_tenant(state).await;
+    }
+
+    let tenants = run_db(state, |conn| tenant::all_tenants(conn).map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)).await?;
+    let mut count = 0;
+    for tenant in tenants {
+        count += tenant::scope(Some(tenant), run_once_for_tenant(state)).await?;
+    }
+    Ok(count)
+}
+
+// one scheduler run in the current tenant's schema (or the only one)
+async fn run_once_for_tenant(state: &AppState) -> Result<usize, axum::http::StatusCode> {
+    let now = Utc::now();
+    let max_todos = state.config.max_todos_per_user;
+    let created = run_db(state, move |conn| {
+        create_next_instances(conn, now, max_todos).map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)
+    })
+    .await?;
+
+    let count = created.len();
+    for todo in created {
+        state.publish(TodoEvent::Created { todo }); // shows up for connected clients like any other new todo
+    }
+    Ok(count)
+}
+
+fn create_next_instances(conn: &mut PgConnection, now: DateTime<Utc>, max_todos: Option<i64>) -> QueryResult<Vec<Todo>> {
+    conn.transaction(|conn| {
+        // the ids that already have a next instance
+        // (a deleted next instance counts too, deleting it is how a series is stopped)
+        // (the subquery reads todos too, so it needs an alias for Diesel to tell the two apart)
+        let successors = diesel::alias!(todos as successors);
+        let recurred_from = successors.field(todos::recurred_from);
+        let spawned = successors.filter(recurred_from.is_not_null()).select(recurred_from);
+
+        // the latest instance of every recurring todo, once it's completed
+        let due = todos::table
+            .filter(live())
+            .filter(todos::recurrence.ne(Recurrence::None))
+            .filter(todos::completed.eq(true))
+            .filter(todos::id.nullable().ne_all(spawned))
+            .order(todos::id.asc())
+            .load::<Todo>(conn)?;
+
+        let mut created = Vec::new();
+        for previous in due {
+            if check_todo_limit(conn, max_todos, 1)?.is_err() {
+                break; // the list is full, the rest wait for a later run
+            }
+            let next = NewTodo {
+                title: previous.title,
+                content: previous.content,
+                due_date: next_due_date(previous.recurrence, previous.due_date, now),
+                recurrence: previous.recurrence,
+            };
+            let position = next_position(conn)?;
+
+            let todo = diesel::insert_into(todos::table)
+                .values((&next, todos::position.eq(position), todos::recurred_from.eq(previous.id)))
+                .on_conflict(todos::recurred_from)
+                .do_nothing() // another run already created it
+                .get_result::<Todo>(conn)
+                .optional()?;
+            created.extend(todo);
+        }
+        Ok(created)
+    })
+}
+
+// move the due date on by whole periods until it's in the future, so a chore that was finished late
+// (or a server that was down for a while) doesn't create instances that are overdue the moment they appear
+// a todo without a due date is due one period from now
+fn next_due_date(recurrence: Recurrence, due_date: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut next = recurrence.next_after(due_date.unwrap_or(now))?;
+    while next <= now {
+        next = recurrence.next_after(next)?;
+    }
+    Some(next)
+}
@@ -1,9 +1,72 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    todos (id) {
+    use diesel::sql_types::*;
+    use super::sql_types::TodoId;
+
+    attachments (id) {
         id -> Int4,
+        todo_id -> TodoId,
+        url -> Text,
+        label -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TodoId;
+
+    todos (id) {
+        id -> TodoId,
         title -> Text,
         content -> Text,
+        completed -> Bool,
+        version -> Int4,
+        position -> Int4,
+        created_at -> Timestamptz,
+        due_date -> Nullable<Timestamptz>,
+        recurrence -> Text,
+        recurred_from -> Nullable<TodoId>,
+        updated_at -> Timestamptz,
+        archived -> Bool,
+        deleted_at -> Nullable<Timestamptz>,
+        external_id -> Nullable<Text>,
     }
 }
+
+diesel::table! {
+    todo_deletions (id) {
+        id -> Bool,
+        last_deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::joinable!(attachments -> todos (todo_id));
+
+diesel::allow_tables_to_appear_in_same_query!(attachments, todo_deletions, todos);
+
+/*
+The SQL types of Postgres full-text search, used by the search functions declared in search.rs.
+todos.search_vector (a generated TSVECTOR column) is left out of the table! above on purpose: every query that loads a Todo
+selects all the table's columns, and the vector is only ever read inside a search, never sent to clients.
+*/
+pub mod sql_types {
+    // the SQL type of a todo id, see TodoId in models.rs
+    #[cfg(not(feature = "uuid-ids"))]
+    pub type TodoId = diesel::sql_types::Int4;
+    #[cfg(feature = "uuid-ids")]
+    pub type TodoId = diesel::sql_types::Uuid;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsquery", schema = "pg_catalog"))]
+    pub struct Tsquery;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "regconfig", schema = "pg_catalog"))]
+    pub struct Regconfig;
+}
@@ -0,0 +1,36 @@
+use diesel::dsl::sql; // raw SQL fragments, for the search_vector column and the 'english' configuration
+use diesel::expression::SqlLiteral;
+use diesel::sql_types::Text; // the SQL type of the query and text arguments
+use crate::schema::sql_types::{Regconfig, Tsquery, Tsvector};
+
+/*
+Diesel has no built-in support for Postgres full-text search, so the functions and operator we need are declared here.
+Each declaration generates a Rust function of the same name that builds the SQL call, type-checked like any other expression.
+*/
+diesel::define_sql_function! {
+    // parse a search query like "milk & !oat" into a tsquery, using the given text search configuration
+    fn to_tsquery(config: Regconfig, query: Text) -> Tsquery;
+}
+
+diesel::define_sql_function! {
+    // how well a document matches a query, higher is better
+    fn ts_rank(document: Tsvector, query: Tsquery) -> Float4;
+}
+
+diesel::define_sql_function! {
+    // an excerpt of the text with the matching words wrapped in <b></b>
+    fn ts_headline(config: Regconfig, text: Text, query: Tsquery) -> Text;
+}
+
+// `document @@ query`, true when the document matches the query
+diesel::infix_operator!(Matches, " @@ ", backend: diesel::pg::Pg);
+
+// the english configuration, the same one the search_vector column is built with (see its migration)
+pub fn english() -> SqlLiteral<Regconfig> {
+    sql::<Regconfig>("'english'")
+}
+
+// the generated todos.search_vector column, which isn't part of the table! in schema.rs
+pub fn search_vector() -> SqlLiteral<Tsvector> {
+    sql::<Tsvector>("todos.search_vector")
+}
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicBool, Ordering}; // a flag every request can read while an admin flips it
+use std::sync::Arc; // Arc lets every handler share the same config without copying it
+
+use axum::extract::FromRef; // lets handlers extract a single piece of the state
+use tokio::sync::broadcast; // fans todo change events out to every WebSocket client
+use crate::cache::TodoCache;
+use crate::config::Config;
+use crate::events::{TodoEvent, EVENT_CHANNEL_CAPACITY};
+use crate::handlers::DbPool;
+use crate::metrics::Metrics;
+use crate::models::TodoId;
+use crate::tenant;
+
+// AppState - everything the handlers share, passed to the router with .with_state()
+// adding a new shared dependency means adding a field here rather than changing every handler signature
+// Clone is required by axum, and is cheap since every field is an Arc (or, for the sender, Arc-like)
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: DbPool, // the database connection pool
+    pub config: Arc<Config>, // settings read at startup
+    pub cache: Option<Arc<TodoCache>>, // the get_todo cache, None when CACHE_ENABLED=false
+    pub events: broadcast::Sender<(Option<String>, TodoEvent)>, // todo changes and the tenant they happened in, pushed to clients connected to /ws
+    pub read_only: Arc<AtomicBool>, // while true, writes are rejected with 503, starts as READ_ONLY
+    pub shutting_down: Arc<AtomicBool>, // set once a shutdown signal arrives, from then on new requests are rejected with 503
+    pub metrics: Arc<Metrics>, // counters served at /metrics
+}
+
+impl AppState {
+    // bundle the pool and config, creating the cache if the config enables it
+    pub fn new(pool: DbPool, config: Config) -> AppState {
+        let cache = config
+            .cache_enabled
+            .then(|| Arc::new(TodoCache::new(config.cache_capacity, config.cache_ttl)));
+
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY); // receivers are created per WebSocket client
+
+        let read_only = Arc::new(AtomicBool::new(config.read_only));
+
+        AppState { pool, config: Arc::new(config), cache, events, read_only, shutting_down: Arc::default(), metrics: Arc::default() }
+    }
+
+    // whether the API is currently in read-only mode
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed) // a lone flag, nothing else is synchronized through it
+    }
+
+    // switch read-only mode on or off for every request from now on
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    // whether the server is draining, see shutdown_guard in middleware.rs
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    // start draining: called once, when the shutdown signal arrives (see main.rs)
+    pub fn start_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    // tell every WebSocket client connected as the current tenant about a change
+    pub fn publish(&self, event: TodoEvent) {
+        let _ = self.events.send((tenant::current(), event)); // fails only when nobody is listening, which is fine
+    }
+
+    // drop one todo from the cache (if caching is on), after it was updated
+    pub fn invalidate_todo(&self, todo_id: TodoId) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(tenant::current().as_deref(), todo_id); // the todo of the tenant being served
+        }
+    }
+
+    // drop every todo from the cache (if caching is on), after a write that touched many of them
+    pub fn invalidate_all_todos(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+}
+
+// allows a handler that only needs the pool to extract State<DbPool> directly
+impl FromRef<AppState> for DbPool {
+    fn from_ref(state: &AppState) -> DbPool {
+        state.pool.clone()
+    }
+}
@@ -0,0 +1,28 @@
+use opentelemetry::trace::TracerProvider as _; // provides tracer() on the SDK's provider
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+
+/*
+Exporting traces to an OpenTelemetry collector, in addition to the logs on stdout.
+When OTEL_EXPORTER_OTLP_ENDPOINT is set (e.g. http://localhost:4318), main.rs adds a tracing-opentelemetry layer built
+from this tracer to the subscriber, and every span becomes an OpenTelemetry span sent to the collector over OTLP/HTTP:
+      - "request", one per request (see request_span in lib.rs), with its method, uri and request_id as attributes
+      - "db_query", a child of it for every database call the handler makes (see run_db in handlers.rs)
+Spans are batched and sent from a background thread, so exporting never holds up a request.
+The exporter reads the endpoint (and the other standard OTEL_EXPORTER_OTLP_* variables, like headers for authentication)
+itself, from the environment. Without the endpoint no layer is added at all, so there's no exporting overhead.
+*/
+pub fn tracer_provider() -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = SpanExporter::builder().with_http().build()?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(env!("CARGO_PKG_NAME")).build()) // how the collector names us
+        .build())
+}
+
+// the tracer the tracing-opentelemetry layer creates its spans with
+pub fn tracer(provider: &SdkTracerProvider) -> SdkTracer {
+    provider.tracer(env!("CARGO_PKG_NAME"))
+}
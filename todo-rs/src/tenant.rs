@@ -0,0 +1,130 @@
+use std::future::Future;
+
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Text};
+use serde_json::json;
+use crate::handlers::{db_error, run_db};
+use crate::state::AppState;
+
+/*
+Multi-tenancy (MULTI_TENANT=true): every customer's todos live in their own Postgres schema, tenant_<id>, with the same
+tables as the default one. Each request names its tenant, either in an X-Tenant-Id header or as the subdomain of
+TENANT_BASE_DOMAIN (acme.todos.example.com is tenant "acme" when the base domain is todos.example.com), and the header wins.
+
+resolve_tenant checks the tenant's schema exists (404 otherwise, the same as for a request that names no tenant at all)
+and runs the rest of the request with it as the current tenant. run_db then points each connection it checks out at that
+schema with SET search_path, so every query in the handlers hits the tenant's tables without naming them, and resets it
+before the connection goes back to the pool. The cache and the WebSocket events are keyed by tenant too, so nothing
+one tenant does is ever seen by another.
+
+A new tenant is set up by creating its schema and running the migrations in it, e.g. with
+      CREATE SCHEMA tenant_acme;
+      diesel migration run --database-url "postgres://...?options=-csearch_path%3Dtenant_acme"
+*/
+
+tokio::task_local! {
+    // the tenant of the request being handled, None when multi-tenancy is off
+    static CURRENT: Option<String>;
+}
+
+// the tenant of the request this is called on behalf of (None outside a request, or when multi-tenancy is off)
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|tenant| tenant.clone()).ok().flatten()
+}
+
+// run `future` as `tenant`, for work that outlives the request that started it (e.g. a streamed response body)
+// or that doesn't come from a request at all (e.g. the scheduler)
+pub async fn scope<F: Future>(tenant: Option<String>, future: F) -> F::Output {
+    CURRENT.scope(tenant, future).await
+}
+
+// the schema a tenant's tables live in
+pub fn schema(tenant: &str) -> String {
+    format!("tenant_{}", tenant)
+}
+
+// tenant ids end up in SQL (as part of a schema name), so only lowercase letters, digits and underscores are allowed,
+// and only as many as fit in a Postgres identifier once "tenant_" is prepended
+fn is_valid_tenant_id(tenant: &str) -> bool {
+    (1..=56).contains(&tenant.len()) && tenant.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+// routes that aren't tenant data, served without a tenant
+const TENANTLESS_PATHS: &[&str] = &["/version", "/metrics"];
+
+// the middleware: find the request's tenant, 404 if there is none or it doesn't exist, otherwise handle the request as it
+pub async fn resolve_tenant(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.config.multi_tenant || TENANTLESS_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let Some(tenant) = requested_tenant(&state, &request) else {
+        return unknown_tenant();
+    };
+    match tenant_exists(&state, &tenant).await {
+        Ok(true) => scope(Some(tenant), next.run(request)).await,
+        Ok(false) => unknown_tenant(),
+        Err(status) => status.into_response(),
+    }
+}
+
+// the tenant id from the X-Tenant-Id header, or else from the Host's subdomain, if it's a valid one
+fn requested_tenant(state: &AppState, request: &Request) -> Option<String> {
+    let from_header = request.headers().get("x-tenant-id").and_then(|value| value.to_str().ok());
+
+    let from_host = || {
+        let base = state.config.tenant_base_domain.as_deref()?;
+        let host = request.headers().get(header::HOST)?.to_str().ok()?;
+        let host = host.split(':').next()?; // without the port
+        host.strip_suffix(base)?.strip_suffix('.')
+    };
+
+    from_header.or_else(from_host).filter(|tenant| is_valid_tenant_id(tenant)).map(str::to_string)
+}
+
+// whether the tenant's schema exists
+async fn tenant_exists(state: &AppState, tenant: &str) -> Result<bool, StatusCode> {
+    let schema = schema(tenant);
+    run_db(state, move |conn| {
+        diesel::select(diesel::dsl::sql::<Bool>("EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = ").bind::<Text, _>(schema).sql(")"))
+            .get_result::<bool>(conn)
+            .map_err(db_error)
+    })
+    .await
+}
+
+fn unknown_tenant() -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "error": "unknown tenant" }))).into_response()
+}
+
+// point a freshly checked out connection at the tenant's schema, or at the default one when there's no tenant
+// (which also undoes any search_path a previous user of the connection failed to reset)
+pub fn set_search_path(conn: &mut PgConnection, tenant: Option<&str>) -> QueryResult<()> {
+    match tenant {
+        Some(tenant) => diesel::sql_query(format!("SET search_path TO \"{}\"", schema(tenant))).execute(conn)?, // safe to format in, see is_valid_tenant_id
+        None => diesel::sql_query("SET search_path TO DEFAULT").execute(conn)?,
+    };
+    Ok(())
+}
+
+// the tenants that exist, i.e. every tenant_<id> schema, for background work that has to visit each of them
+pub fn all_tenants(conn: &mut PgConnection) -> QueryResult<Vec<String>> {
+    #[derive(QueryableByName)]
+    struct Schema {
+        #[diesel(sql_type = Text)]
+        nspname: String,
+    }
+
+    let schemas = diesel::sql_query("SELECT nspname FROM pg_namespace WHERE nspname LIKE 'tenant\\_%' ORDER BY nspname")
+        .load::<Schema>(conn)?;
+    Ok(schemas
+        .into_iter()
+        .filter_map(|schema| schema.nspname.strip_prefix("tenant_").map(str::to_string))
+        .filter(|tenant| is_valid_tenant_id(tenant))
+        .collect())
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+
+use common::{send, send_with_headers, test_state_with};
+
+// a JWT signed with the test JWT_SECRET, for a caller with the given role, expiring `expires_in` seconds from now
+fn jwt(role: &str, expires_in: i64) -> String {
+    let claims = json!({ "sub": "alice", "role": role, "exp": chrono::Utc::now().timestamp() + expires_in });
+    let key = jsonwebtoken::EncodingKey::from_secret(b"jwt-secret");
+    jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+}
+
+#[tokio::test]
+async fn reset_empties_the_database_and_restarts_ids() {
+    let app = todo_rs::app(test_state_with(|config| {
+        config.allow_dev_endpoints = true;
+        config.admin_token = Some("secret".to_string());
+    }));
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "second", "content": "" }))).await;
+
+    let (status, _) = send(&app, Method::POST, "/admin/reset", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, body) = send_with_headers(&app, Method::POST, "/admin/reset", &[("authorization", "Bearer secret")], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["deleted"].as_u64().unwrap() >= 2); // and whatever else the test database held
+
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(todos, json!([]));
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "fresh", "content": "" }))).await;
+    assert_eq!(todo["id"], 1);
+}
+
+#[tokio::test]
+async fn reset_does_not_exist_unless_dev_endpoints_are_allowed() {
+    let app = todo_rs::app(test_state_with(|config| config.allow_dev_endpoints = false));
+
+    let (status, _) = send(&app, Method::POST, "/admin/reset", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admin_endpoints_need_a_jwt_with_the_admin_role() {
+    let app = todo_rs::app(test_state_with(|config| {
+        config.admin_token = None;
+        config.jwt_secret = Some("jwt-secret".to_string());
+    }));
+    let body = Some(json!({ "read_only": false }));
+
+    for (token, expected) in [
+        (jwt("admin", 60), StatusCode::OK),
+        (jwt("user", 60), StatusCode::FORBIDDEN),
+        (jwt("admin", -120), StatusCode::UNAUTHORIZED), // expired, past jsonwebtoken's 60 seconds of leeway
+        ("not-a-jwt".to_string(), StatusCode::UNAUTHORIZED),
+    ] {
+        let authorization = format!("Bearer {}", token);
+        let (status, _, _) = send_with_headers(&app, Method::POST, "/admin/read-only", &[("authorization", &authorization)], body.clone()).await;
+        assert_eq!(status, expected, "for {}", token);
+    }
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use axum::http::{Method, StatusCode};
+
+use common::{send, send_with_headers, test_state, test_state_with};
+
+// Runs on Tokio's default single-threaded test runtime, so if a handler blocked its thread while waiting for a
+// database connection, nothing else (including the timer below) could run until the handler gave up.
+#[tokio::test]
+async fn waiting_for_a_connection_does_not_block_the_runtime() {
+    let state = test_state();
+    let app = todo_rs::app(state.clone());
+
+    // take the pool's only connection, so the request below has to wait for it
+    let held = state.pool.get().unwrap();
+    let request = tokio::spawn(async move { send(&app, Method::GET, "/todos", None).await });
+
+    // while the request waits, the runtime should still run other tasks on time
+    let started = Instant::now();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(started.elapsed() < Duration::from_millis(500), "the runtime was blocked for {:?}", started.elapsed());
+
+    // once the connection is handed back, the request goes through
+    drop(held);
+    let (status, _) = request.await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn saturated_pool_sheds_requests_with_retry_after() {
+    let state = test_state_with(|config| config.pool_shed_after = Duration::from_millis(20));
+    let app = todo_rs::app(state.clone());
+
+    // with the only connection taken, the request is shed after 20ms rather than waiting out the pool's connection timeout
+    let held = state.pool.get().unwrap();
+    let started = Instant::now();
+    let (status, headers, _) = send_with_headers(&app, Method::GET, "/todos", &[], None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(headers["retry-after"], "1");
+    assert!(started.elapsed() < Duration::from_secs(1), "shedding took {:?}", started.elapsed());
+    drop(held);
+
+    let (_, _, metrics) = send_with_headers(&app, Method::GET, "/metrics", &[], None).await;
+    assert!(metrics.as_str().unwrap().contains("todo_requests_shed_total 1\n"));
+}
@@ -0,0 +1,107 @@
+// Shared helpers for the integration tests.
+//
+// The tests run against a real PostgreSQL database given by DATABASE_URL (point it at a throwaway test database, not your dev one).
+// Pending migrations are run once per test binary, then every test gets its own single-connection pool whose connection
+// is inside a test transaction that is never committed, so nothing a test writes outlives it and tests can't see each other's rows.
+
+#![allow(dead_code)] // each test binary uses a different subset of these helpers
+
+use std::sync::{Arc, Once};
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use serde_json::Value;
+use todo_rs::config::Config;
+use todo_rs::state::AppState;
+use tower::ServiceExt; // provides oneshot() to send a single request through the router
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+static MIGRATE: Once = Once::new();
+
+fn database_url() -> String {
+    std::env::var("DATABASE_URL").expect("DATABASE_URL must be set to a test database to run the integration tests")
+}
+
+// opens every pooled connection inside a transaction that is rolled back when the connection is dropped
+#[derive(Debug)]
+struct TestTransaction;
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for TestTransaction {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        conn.begin_test_transaction().map_err(r2d2::Error::QueryError)
+    }
+}
+
+// builds the app router backed by the test database, the same way main.rs does
+pub fn test_app() -> Router {
+    todo_rs::app(test_state())
+}
+
+// the state test_app uses, for tests that also need to reach the pool directly
+pub fn test_state() -> AppState {
+    test_state_with(|_| {})
+}
+
+// like test_state, with settings changed from what the environment gives, e.g. test_state_with(|config| config.read_only = true)
+pub fn test_state_with(configure: impl FnOnce(&mut Config)) -> AppState {
+    MIGRATE.call_once(|| {
+        let mut conn = PgConnection::establish(&database_url()).expect("failed to connect to the test database");
+        conn.run_pending_migrations(MIGRATIONS).expect("failed to run migrations");
+    });
+
+    // a single connection, so every request in the test shares the same test transaction
+    let pool = r2d2::Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(TestTransaction))
+        .build(ConnectionManager::<PgConnection>::new(database_url()))
+        .expect("failed to create test pool");
+
+    let mut config = Config::from_env().expect("invalid test configuration");
+    configure(&mut config);
+    AppState::new(Arc::new(pool), config)
+}
+
+// sends one request through the router and returns the status and the JSON body
+// (Null when the body is empty, and a string for a plain-text body like axum's extractor rejections)
+pub async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let (status, _, json) = send_with_headers(app, method, uri, &[], body).await;
+    (status, json)
+}
+
+// like send, but with extra request headers, and also returns the response headers
+pub async fn send_with_headers(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> (StatusCode, HeaderMap, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+    };
+
+    (status, headers, json)
+}
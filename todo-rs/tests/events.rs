@@ -0,0 +1,47 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use futures::StreamExt; // next() on the WebSocket stream
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+use common::{send, test_app};
+
+#[tokio::test]
+async fn websocket_receives_todo_change_events() {
+    let app = test_app();
+
+    // WebSockets need a real connection to upgrade, so serve the app on a random local port
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move { axum::serve(listener, server).await.unwrap() });
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+
+    // waits for the next event pushed over the socket
+    let mut next_event = async || -> Value {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+        match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text message, got {:?}", other),
+        }
+    };
+
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "live", "content": "" }))).await;
+    let event = next_event().await;
+    assert_eq!(event["type"], "created");
+    assert_eq!(event["todo"], todo);
+
+    send(&app, Method::POST, &format!("/todos/{}", todo["id"]), Some(json!({ "title": "live!", "content": "" }))).await;
+    let event = next_event().await;
+    assert_eq!(event["type"], "updated");
+    assert_eq!(event["todo"]["title"], "live!");
+
+    let (status, _) = send(&app, Method::DELETE, &format!("/todos/{}", todo["id"]), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(next_event().await, json!({ "type": "deleted", "id": todo["id"] }));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use axum::http::Method;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use serde_json::json;
+use todo_rs::schema::todos;
+
+use common::{send, test_state};
+
+#[tokio::test]
+async fn only_todos_deleted_long_enough_ago_are_purged() {
+    let state = test_state();
+    let app = todo_rs::app(state.clone());
+
+    for title in ["old", "recent", "kept"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+    }
+    let (_, listed) = send(&app, Method::GET, "/todos", None).await;
+    for todo in &listed.as_array().unwrap()[..2] {
+        send(&app, Method::DELETE, &format!("/todos/{}", todo["id"]), None).await;
+    }
+
+    // pretend "old" was deleted 40 days ago, past the default PURGE_AFTER_DAYS of 30
+    let mut conn = state.pool.get().unwrap();
+    diesel::update(todos::table.filter(todos::title.eq("old")))
+        .set(todos::deleted_at.eq(chrono::Utc::now() - chrono::Duration::days(40)))
+        .execute(&mut conn)
+        .unwrap();
+    drop(conn);
+
+    assert_eq!(todo_rs::purge::run_once(&state).await, Ok(Some(1)));
+    assert_eq!(todo_rs::purge::run_once(&state).await, Ok(Some(0)));
+
+    let mut conn = state.pool.get().unwrap();
+    let left = todos::table.select(todos::title).order(todos::id).load::<String>(&mut conn).unwrap();
+    assert_eq!(left, ["recent", "kept"]); // "recent" is still there, only soft-deleted
+}
+
+#[tokio::test]
+async fn purge_is_skipped_while_another_instance_holds_the_lock() {
+    let state = test_state();
+
+    // another instance, on a connection of its own
+    let mut other = PgConnection::establish(&std::env::var("DATABASE_URL").unwrap()).unwrap();
+    diesel::sql_query("SELECT pg_advisory_lock($1)").bind::<BigInt, _>(todo_rs::purge::PURGE_LOCK_KEY).execute(&mut other).unwrap();
+
+    assert_eq!(todo_rs::purge::run_once(&state).await, Ok(None));
+
+    diesel::sql_query("SELECT pg_advisory_unlock($1)").bind::<BigInt, _>(todo_rs::purge::PURGE_LOCK_KEY).execute(&mut other).unwrap();
+    assert_eq!(todo_rs::purge::run_once(&state).await, Ok(Some(0)));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+
+use common::{send, send_with_headers, test_state_with};
+
+#[tokio::test]
+async fn read_only_mode_rejects_writes_but_serves_reads() {
+    let state = test_state_with(|config| config.read_only = true);
+    let app = todo_rs::app(state.clone());
+
+    let (status, body) = send(&app, Method::POST, "/todos", Some(json!({ "title": "nope", "content": "" }))).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, json!({ "error": "read-only mode" }));
+
+    let (status, _) = send(&app, Method::DELETE, "/todos/1", None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let (status, _) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    state.set_read_only(false);
+    let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": "yes", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn admin_can_toggle_read_only_mode() {
+    let state = test_state_with(|config| config.admin_token = Some("secret".to_string()));
+    let app = todo_rs::app(state.clone());
+    let on = Some(json!({ "read_only": true }));
+
+    let (status, _, _) = send_with_headers(&app, Method::POST, "/admin/read-only", &[("authorization", "Bearer wrong")], on.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(!state.is_read_only());
+
+    let (status, _, body) = send_with_headers(&app, Method::POST, "/admin/read-only", &[("authorization", "Bearer secret")], on).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "read_only": true }));
+    let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": "nope", "content": "" }))).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // the toggle itself still works while writes are rejected
+    let off = Some(json!({ "read_only": false }));
+    let (status, _, _) = send_with_headers(&app, Method::POST, "/admin/read-only", &[("authorization", "Bearer secret")], off).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!state.is_read_only());
+}
+
+#[tokio::test]
+async fn admin_endpoints_are_disabled_without_a_token() {
+    let app = todo_rs::app(test_state_with(|config| config.admin_token = None));
+
+    let (status, _) = send(&app, Method::POST, "/admin/read-only", Some(json!({ "read_only": true }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
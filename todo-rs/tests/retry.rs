@@ -0,0 +1,99 @@
+mod common;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error};
+use todo_rs::retry::with_retry;
+
+use common::test_state_with;
+
+// the error Postgres gives the loser of two conflicting serializable transactions
+fn serialization_failure() -> Error {
+    Error::DatabaseError(DatabaseErrorKind::SerializationFailure, Box::new("could not serialize access".to_string()))
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried_until_the_query_succeeds() {
+    let state = test_state_with(|config| config.db_retry_base_delay = Duration::from_millis(1));
+    let attempts = Arc::new(AtomicU32::new(0));
+
+    let counter = attempts.clone();
+    let result = with_retry(&state, move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+        0 | 1 => Err(serialization_failure()),
+        _ => Ok("done"),
+    })
+    .await;
+
+    assert_eq!(result, Ok("done"));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn retries_stop_after_db_retries() {
+    let state = test_state_with(|config| {
+        config.db_retries = 2;
+        config.db_retry_base_delay = Duration::from_millis(1);
+    });
+    let attempts = Arc::new(AtomicU32::new(0));
+
+    let counter = attempts.clone();
+    let result = with_retry(&state, move |_| -> Result<(), Error> {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Err(serialization_failure())
+    })
+    .await;
+
+    assert_eq!(result, Err(StatusCode::INTERNAL_SERVER_ERROR));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3); // the first try and 2 retries
+}
+
+#[tokio::test]
+async fn other_errors_fail_immediately() {
+    let state = test_state_with(|config| config.db_retry_base_delay = Duration::from_millis(1));
+
+    for (error, expected) in [
+        (Error::NotFound, StatusCode::NOT_FOUND),
+        (Error::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new("duplicate key".to_string())), StatusCode::INTERNAL_SERVER_ERROR),
+    ] {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        let mut error = Some(error);
+        let result = with_retry(&state, move |_| -> Result<(), Error> {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Err(error.take().unwrap()) // a second attempt would panic here
+        })
+        .await;
+
+        assert_eq!(result, Err(expected));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[tokio::test]
+async fn position_collisions_are_retried_then_409() {
+    let state = test_state_with(|config| {
+        config.db_retries = 2;
+        config.db_retry_base_delay = Duration::from_millis(1);
+    });
+    let attempts = Arc::new(AtomicU32::new(0));
+
+    // two live todos at one position, which is what two racing moves would leave behind; the constraint is deferred
+    // to the commit, which a test transaction never gets to, so it's checked right away instead
+    let counter = attempts.clone();
+    let result = with_retry(&state, move |conn| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        conn.transaction::<_, Error, _>(|conn| {
+            diesel::sql_query("INSERT INTO todos (title, content, position) VALUES ('a', '', 0), ('b', '', 0)").execute(conn)?;
+            diesel::sql_query("SET CONSTRAINTS todos_position_unique IMMEDIATE").execute(conn)?;
+            Ok(())
+        })
+    })
+    .await;
+
+    assert_eq!(result, Err(StatusCode::CONFLICT));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+
+use common::{send, test_state};
+
+#[tokio::test]
+async fn completed_recurring_todo_spawns_one_next_instance() {
+    let state = test_state();
+    let app = todo_rs::app(state.clone());
+
+    // due tomorrow, so the next instance is due a week after that
+    let due = (Utc::now() + Duration::days(1)).to_rfc3339();
+    let (_, chore) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Water plants", "content": "", "due_date": due, "recurrence": "weekly" }))).await;
+    assert_eq!(chore["recurrence"], "weekly");
+
+    // nothing happens until it's completed
+    assert_eq!(todo_rs::scheduler::run_once(&state).await, Ok(0));
+
+    let uri = format!("/todos/{}", chore["id"]);
+    send(&app, Method::POST, &uri, Some(json!({ "title": "Water plants", "content": "", "completed": true }))).await;
+    assert_eq!(todo_rs::scheduler::run_once(&state).await, Ok(1));
+
+    // a second run (e.g. after a restart) doesn't create it again
+    assert_eq!(todo_rs::scheduler::run_once(&state).await, Ok(0));
+
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let todos = todos.as_array().unwrap();
+    assert_eq!(todos.len(), 2);
+    let next = &todos[1];
+    assert_eq!(next["title"], "Water plants");
+    assert_eq!(next["completed"], false);
+    assert_eq!(next["recurrence"], "weekly");
+    assert_eq!(next["recurred_from"], chore["id"]);
+    let due_at = |todo: &serde_json::Value| todo["due_date"].as_str().unwrap().parse::<DateTime<Utc>>().unwrap();
+    assert_eq!(due_at(next) - due_at(&chore), Duration::weeks(1));
+}
+
+#[tokio::test]
+async fn unknown_recurrence_is_rejected() {
+    let app = todo_rs::app(test_state());
+
+    let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Water plants", "content": "", "recurrence": "hourly" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+
+use common::{send, send_with_headers, test_state};
+
+#[tokio::test]
+async fn requests_are_rejected_once_shutdown_starts() {
+    let state = test_state();
+    let app = todo_rs::app(state.clone());
+
+    let (status, _) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    state.start_shutdown();
+    let (status, headers, body) = send_with_headers(&app, Method::GET, "/todos", &[], None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, json!({ "error": "shutting down" }));
+    assert_eq!(headers["connection"], "close");
+    assert!(headers.contains_key("retry-after"));
+
+    let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": "too late", "content": "" }))).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
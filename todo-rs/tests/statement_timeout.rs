@@ -0,0 +1,38 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use todo_rs::retry::with_retry;
+
+use common::test_state_with;
+
+// the statement_timeout the connection is currently running with
+fn current_timeout(conn: &mut PgConnection) -> QueryResult<String> {
+    diesel::select(sql::<Text>("current_setting('statement_timeout')")).get_result(conn)
+}
+
+#[tokio::test]
+async fn the_timeout_applies_to_queries_and_is_reset_afterwards() {
+    let state = test_state_with(|config| config.statement_timeout = Some(Duration::from_millis(50)));
+
+    // while a query runs through the app, the timeout is set
+    let during = with_retry(&state, current_timeout).await;
+    assert_eq!(during, Ok("50ms".to_string()));
+
+    // and the next user of the connection doesn't inherit it
+    let mut conn = state.pool.get().unwrap();
+    assert_eq!(current_timeout(&mut conn).unwrap(), "0");
+}
+
+#[tokio::test]
+async fn a_query_running_past_the_timeout_is_cancelled_with_504() {
+    let state = test_state_with(|config| config.statement_timeout = Some(Duration::from_millis(50)));
+
+    let result = with_retry(&state, |conn| diesel::sql_query("SELECT pg_sleep(1)").execute(conn)).await;
+
+    assert_eq!(result, Err(StatusCode::GATEWAY_TIMEOUT));
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use diesel::connection::SimpleConnection; // batch_execute, to run several statements at once
+use serde_json::json;
+use todo_rs::state::AppState;
+use common::{send, send_with_headers, test_state_with};
+
+// a multi-tenant app with two tenants, acme and globex, whose schemas are copies of the default one
+// (created inside the test transaction, so they're gone again after the test)
+fn two_tenant_state() -> AppState {
+    let state = test_state_with(|config| {
+        config.multi_tenant = true;
+        config.tenant_base_domain = Some("todos.test".to_string());
+    });
+
+    let mut conn = state.pool.get().unwrap();
+    for tenant in ["acme", "globex"] {
+        conn.batch_execute(&format!(
+            "CREATE SCHEMA tenant_{tenant};
+             CREATE TABLE tenant_{tenant}.todos (LIKE public.todos INCLUDING ALL);
+             CREATE TABLE tenant_{tenant}.todo_deletions (LIKE public.todo_deletions INCLUDING ALL);
+             INSERT INTO tenant_{tenant}.todo_deletions (id) VALUES (true);"
+        ))
+        .unwrap();
+    }
+    state
+}
+
+#[tokio::test]
+async fn tenants_only_see_their_own_todos() {
+    let app = todo_rs::app(two_tenant_state());
+    let acme = [("x-tenant-id", "acme")];
+    let globex = [("x-tenant-id", "globex")];
+
+    let (status, _, created) =
+        send_with_headers(&app, Method::POST, "/todos", &acme, Some(json!({ "title": "Acme", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let uri = format!("/todos/{}", created["id"]);
+
+    let (_, _, listed) = send_with_headers(&app, Method::GET, "/todos", &acme, None).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    let (_, _, listed) = send_with_headers(&app, Method::GET, "/todos", &globex, None).await;
+    assert_eq!(listed, json!([]));
+
+    // the same id means nothing in another tenant, even with the todo cached for acme
+    let (status, _, _) = send_with_headers(&app, Method::GET, &uri, &acme, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send_with_headers(&app, Method::GET, &uri, &globex, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // the tenant can come from the subdomain instead of the header
+    let (_, _, listed) = send_with_headers(&app, Method::GET, "/todos", &[("host", "acme.todos.test:3000")], None).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn requests_without_a_known_tenant_are_not_found() {
+    let app = todo_rs::app(two_tenant_state());
+
+    let (status, body) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, json!({ "error": "unknown tenant" }));
+
+    for tenant in ["initech", "Acme", "acme;drop"] {
+        let (status, _, _) = send_with_headers(&app, Method::GET, "/todos", &[("x-tenant-id", tenant)], None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "tenant {:?}", tenant);
+    }
+
+    // routes that aren't tenant data don't need one
+    let (status, _) = send(&app, Method::GET, "/version", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,1282 @@
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Method, Request, StatusCode};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use serde_json::json;
+use todo_rs::models::{SortOrder, TodoSort};
+use tower::ServiceExt;
+
+use common::{send, send_with_headers, test_app, test_state, test_state_with};
+
+#[tokio::test]
+async fn create_get_update_delete_round_trip() {
+    let app = test_app();
+
+    let (status, created) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Buy milk", "content": "2 litres" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["title"], "Buy milk");
+    assert_eq!(created["completed"], false);
+    let uri = format!("/todos/{}", created["id"]);
+
+    let (status, fetched) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched, created);
+
+    let (status, updated) = send(&app, Method::POST, &uri, Some(json!({ "title": "Buy oat milk", "content": "2 litres", "completed": true }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["title"], "Buy oat milk");
+    assert_eq!(updated["completed"], true);
+
+    let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn titles_and_contents_are_normalized() {
+    let app = test_app();
+
+    let body = json!({ "title": "  Buy \t  oat\n milk ", "content": "Shop list:\n  - oat milk  \n\n" });
+    let (status, created) = send(&app, Method::POST, "/todos", Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["title"], "Buy oat milk");
+    assert_eq!(created["content"], "Shop list:\n  - oat milk"); // only the trailing whitespace goes
+
+    let uri = format!("/todos/{}", created["id"]);
+    let (status, updated) = send(&app, Method::POST, &uri, Some(json!({ "title": " Buy  milk", "content": "" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["title"], "Buy milk");
+
+    // a title that's nothing but whitespace is empty
+    let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": " \n ", "content": "" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = send(&app, Method::POST, &uri, Some(json!({ "title": "", "content": "" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn list_and_count_respect_completed_filter() {
+    let app = test_app();
+
+    for title in ["one", "two", "three"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+    }
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let first = &todos.as_array().unwrap()[0];
+    send(&app, Method::POST, &format!("/todos/{}", first["id"]), Some(json!({ "title": "one", "content": "", "completed": true }))).await;
+
+    let (status, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(count, json!({ "count": 3 }));
+
+    let (_, count) = send(&app, Method::GET, "/todos/count?completed=true", None).await;
+    assert_eq!(count, json!({ "count": 1 }));
+
+    let (_, pending) = send(&app, Method::GET, "/todos?completed=false", None).await;
+    assert_eq!(pending.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn missing_todo_returns_404() {
+    let app = test_app();
+
+    let (status, _) = send(&app, Method::GET, "/todos/999999", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(&app, Method::POST, "/todos/999999", Some(json!({ "title": "x", "content": "y" }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(&app, Method::DELETE, "/todos/999999", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn malformed_todo_id_returns_400() {
+    let app = test_app();
+
+    let (status, body) = send(&app, Method::GET, "/todos/abc", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, json!({ "error": "invalid path parameter", "field": "id", "detail": "id must be a positive integer" }));
+
+    let (status, _) = send(&app, Method::POST, "/todos/12ab/archive", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(&app, Method::DELETE, "/todos/not-an-id/attachments/1", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn ids_that_cannot_exist_are_400_not_404() {
+    let app = test_app();
+
+    for (method, uri) in [(Method::GET, "/todos/0"), (Method::POST, "/todos/-3"), (Method::DELETE, "/todos/-1")] {
+        let (status, body) = send(&app, method.clone(), uri, Some(json!({ "title": "x", "content": "y" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{} {}", method, uri);
+        assert_eq!(body["detail"], "id must be a positive integer", "{} {}", method, uri);
+    }
+
+    // each id of a path is checked, and the error names the one that's wrong
+    let (status, body) = send(&app, Method::DELETE, "/todos/1/attachments/0", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["field"], "attachment_id");
+    let (_, body) = send(&app, Method::DELETE, "/todos/1/attachments/x", None).await;
+    assert_eq!(body["detail"], "attachment_id must be a positive integer");
+}
+
+#[tokio::test]
+async fn version_goes_up_by_one_on_every_change_and_can_be_sorted_by() {
+    let app = test_app();
+
+    let (_, churned) = send(&app, Method::POST, "/todos", Some(json!({ "title": "churned", "content": "" }))).await;
+    let (_, quiet) = send(&app, Method::POST, "/todos", Some(json!({ "title": "quiet", "content": "" }))).await;
+    assert_eq!(churned["version"], 1);
+
+    // an edit, a completion and archiving each count as a change, a move doesn't: it isn't an edit to the todo
+    let uri = format!("/todos/{}", churned["id"]);
+    let mut versions = Vec::new();
+    for n in 0..3 {
+        let (_, todo) = send(&app, Method::POST, &uri, Some(json!({ "title": "churned", "content": format!("edit {}", n) }))).await;
+        versions.push(todo["version"].as_i64().unwrap());
+    }
+    send(&app, Method::POST, "/todos/complete", Some(json!({ "ids": [churned["id"]] }))).await;
+    send(&app, Method::POST, &format!("{}/archive", uri), None).await;
+    send(&app, Method::POST, &format!("{}/unarchive", uri), None).await;
+    let (_, todo) = send(&app, Method::GET, &uri, None).await;
+    versions.push(todo["version"].as_i64().unwrap());
+    let (_, todo) = send(&app, Method::POST, &format!("{}/move", uri), Some(json!({ "position": 1 }))).await;
+    versions.push(todo["version"].as_i64().unwrap());
+    assert_eq!(versions, [2, 3, 4, 7, 7]);
+
+    // nor does being shifted up by that move
+    let (_, todo) = send(&app, Method::GET, &format!("/todos/{}", quiet["id"]), None).await;
+    assert_eq!(todo["version"], 1);
+
+    let (_, todos) = send(&app, Method::GET, "/todos?sort=version&order=desc", None).await;
+    let ids = todos.as_array().unwrap().iter().map(|todo| todo["id"].clone()).collect::<Vec<_>>();
+    assert_eq!(ids, [churned["id"].clone(), quiet["id"].clone()]);
+}
+
+#[tokio::test]
+async fn update_with_stale_if_match_returns_412() {
+    let app = test_app();
+
+    let (_, created) = send(&app, Method::POST, "/todos", Some(json!({ "title": "draft", "content": "" }))).await;
+    let uri = format!("/todos/{}", created["id"]);
+    let (_, headers, _) = send_with_headers(&app, Method::GET, &uri, &[], None).await;
+    let etag = headers["etag"].to_str().unwrap().to_string();
+
+    // first tab saves with the ETag it loaded, which bumps the version
+    let (status, headers, _) = send_with_headers(&app, Method::POST, &uri, &[("if-match", &etag)], Some(json!({ "title": "tab one", "content": "" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(headers["etag"].to_str().unwrap(), etag);
+
+    // second tab still has the old ETag, so its save must not overwrite the first one
+    let (status, _, _) = send_with_headers(&app, Method::POST, &uri, &[("if-match", &etag)], Some(json!({ "title": "tab two", "content": "" }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+    let (_, todo) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(todo["title"], "tab one");
+}
+
+#[tokio::test]
+async fn move_reorders_and_keeps_positions_contiguous() {
+    let app = test_app();
+
+    let mut ids = Vec::new();
+    for title in ["a", "b", "c", "d"] {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+        ids.push(todo["id"].clone());
+    }
+
+    // move "d" to the top, then "a" (now second) past the end
+    let (status, moved) = send(&app, Method::POST, &format!("/todos/{}/move", ids[3]), Some(json!({ "position": 0 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(moved["position"], 0);
+    send(&app, Method::POST, &format!("/todos/{}/move", ids[0]), Some(json!({ "position": 99 }))).await;
+
+    // delete "b" to check the gap closes up
+    send(&app, Method::DELETE, &format!("/todos/{}", ids[1]), None).await;
+
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let order: Vec<(&str, i64)> = todos
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| (todo["title"].as_str().unwrap(), todo["position"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(order, vec![("d", 0), ("c", 1), ("a", 2)]);
+}
+
+#[tokio::test]
+async fn clear_deletes_completed_and_needs_confirm_for_everything() {
+    let app = test_app();
+
+    for (title, done) in [("a", true), ("b", false), ("c", true)] {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+        send(&app, Method::POST, &format!("/todos/{}", todo["id"]), Some(json!({ "title": title, "content": "", "completed": done }))).await;
+    }
+
+    let (status, _) = send(&app, Method::DELETE, "/todos", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = send(&app, Method::DELETE, "/todos?completed=true", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "deleted": 2 }));
+
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(todos[0]["title"], "b");
+    assert_eq!(todos[0]["position"], 0);
+
+    let (_, body) = send(&app, Method::DELETE, "/todos?confirm=true", None).await;
+    assert_eq!(body, json!({ "deleted": 1 }));
+}
+
+#[tokio::test]
+async fn dry_run_deletes_report_without_deleting() {
+    let app = test_app();
+
+    let mut ids = Vec::new();
+    for (title, done) in [("a", true), ("b", false), ("c", true)] {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+        send(&app, Method::POST, &format!("/todos/{}", todo["id"]), Some(json!({ "title": title, "content": "", "completed": done }))).await;
+        ids.push(todo["id"].clone());
+    }
+
+    let (status, body) = send(&app, Method::DELETE, "/todos?completed=true&dry_run=true", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["dry_run"], true);
+    assert_eq!(body["deleted"], 2);
+    let titles: Vec<&str> = body["todos"].as_array().unwrap().iter().map(|todo| todo["title"].as_str().unwrap()).collect();
+    assert_eq!(titles, vec!["a", "c"]);
+
+    let (status, body) = send(&app, Method::DELETE, &format!("/todos/{}?dry_run=true", ids[1]), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted"], 1);
+    assert_eq!(body["todos"][0]["id"], ids[1]);
+
+    let (status, _) = send(&app, Method::DELETE, "/todos/999999?dry_run=true", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // nothing was deleted, or moved
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let positions: Vec<i64> = todos.as_array().unwrap().iter().map(|todo| todo["position"].as_i64().unwrap()).collect();
+    assert_eq!(positions, vec![0, 1, 2]);
+}
+
+#[tokio::test]
+async fn duplicate_copies_title_and_content_as_a_fresh_todo() {
+    let app = test_app();
+
+    let (_, source) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Weekly review", "content": "inbox zero" }))).await;
+    send(&app, Method::POST, &format!("/todos/{}", source["id"]), Some(json!({ "title": "Weekly review", "content": "inbox zero", "completed": true }))).await;
+
+    let (status, copy) = send(&app, Method::POST, &format!("/todos/{}/duplicate", source["id"]), None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_ne!(copy["id"], source["id"]);
+    assert_eq!(copy["title"], "Weekly review (copy)");
+    assert_eq!(copy["content"], "inbox zero");
+    assert_eq!(copy["completed"], false);
+    assert_eq!(copy["position"], 1);
+
+    let (status, _) = send(&app, Method::POST, "/todos/999999/duplicate", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn list_as_csv_when_accept_is_text_csv() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "Milk, eggs", "content": "the \"good\" eggs" }))).await;
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/todos").header("accept", "text/csv").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/csv; charset=utf-8");
+    assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"todos.csv\"");
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let csv = String::from_utf8(body.to_vec()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "id,title,content,completed,created_at");
+    assert!(lines[1].contains(r#","Milk, eggs","the ""good"" eggs",false,"#));
+}
+
+#[tokio::test]
+async fn list_filters_by_created_at_window() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "now", "content": "" }))).await;
+
+    let (_, todos) = send(&app, Method::GET, "/todos?created_after=2000-01-01T00:00:00Z&created_before=2999-01-01T00:00:00%2B02:00", None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 1);
+
+    let (_, todos) = send(&app, Method::GET, "/todos?created_before=2000-01-01T00:00:00Z", None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 0);
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/todos?created_after=yesterday").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("\"yesterday\""));
+}
+
+#[tokio::test]
+async fn invalid_filters_are_400_but_no_matches_is_an_empty_200() {
+    let app = test_app();
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "open", "content": "" }))).await;
+
+    // a valid filter that nothing matches
+    let (status, todos) = send(&app, Method::GET, "/todos?completed=true", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todos, json!([]));
+
+    for query in [
+        "completed=maybe",
+        "completed=",
+        "archived=1",
+        "created_after=2025-13-01T00:00:00Z",
+        "created_after=2025-06-02T00:00:00Z&created_before=2025-06-01T00:00:00Z", // ends before it starts
+        "sort=colour",
+        "order=sideways",
+    ] {
+        let (status, _) = send(&app, Method::GET, &format!("/todos?{}", query), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "GET /todos?{}", query);
+    }
+
+    // the count endpoint takes the same filters
+    let (status, _) = send(&app, Method::GET, "/todos/count?completed=maybe", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = send(&app, Method::GET, "/todos/count?completed=true", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "count": 0 }));
+}
+
+#[tokio::test]
+async fn stats_counts_by_status() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "late", "content": "", "due_date": "2001-01-01T00:00:00Z" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "later", "content": "", "due_date": "2999-01-01T00:00:00Z" }))).await;
+    let (_, done) = send(&app, Method::POST, "/todos", Some(json!({ "title": "done", "content": "", "due_date": "2001-01-01T00:00:00Z" }))).await;
+    send(&app, Method::POST, &format!("/todos/{}", done["id"]), Some(json!({ "title": "done", "content": "", "completed": true }))).await;
+
+    let (status, stats) = send(&app, Method::GET, "/todos/stats", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats, json!({ "total": 3, "completed": 1, "pending": 2, "overdue": 1 }));
+}
+
+#[tokio::test]
+async fn version_reports_build_info() {
+    let app = test_app();
+
+    let (status, version) = send(&app, Method::GET, "/version", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!version["git_sha"].as_str().unwrap().is_empty());
+    assert!(chrono::DateTime::parse_from_rfc3339(version["build_time"].as_str().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn complete_marks_listed_todos_done_and_skips_unknown_ids() {
+    let app = test_app();
+
+    let (_, first) = send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "" }))).await;
+    let (_, second) = send(&app, Method::POST, "/todos", Some(json!({ "title": "second", "content": "" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "third", "content": "" }))).await;
+
+    let ids = json!({ "ids": [first["id"], second["id"], 999_999] });
+    let (status, body) = send(&app, Method::POST, "/todos/complete", Some(ids)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "updated": 2 }));
+
+    let (_, count) = send(&app, Method::GET, "/todos/count?completed=true", None).await;
+    assert_eq!(count["count"], 2);
+
+    let (status, _) = send(&app, Method::POST, "/todos/complete", Some(json!({ "ids": [] }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn partial_bulk_writes_report_on_every_item() {
+    let app = test_app();
+
+    let records = json!({ "todos": [
+        { "title": "good", "content": "" },
+        { "content": "no title" },
+        { "title": "also good", "content": "" },
+    ] });
+    let (status, results) = send(&app, Method::POST, "/todos/import?partial=true", Some(records.clone())).await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(results[0]["status"], "ok");
+    assert_eq!(results[1], json!({ "index": 1, "status": "error", "reason": "missing field `title`" }));
+    assert_eq!(results[2]["status"], "ok");
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 2);
+
+    let (status, _) = send(&app, Method::POST, "/todos/import?partial=true&mode=replace", Some(records)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let ids = json!({ "ids": [results[2]["id"], 999_999, results[0]["id"]] });
+    let (status, completed) = send(&app, Method::POST, "/todos/complete?partial=true", Some(ids)).await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(completed, json!([
+        { "index": 0, "status": "ok", "id": results[2]["id"] },
+        { "index": 1, "status": "error", "reason": "not found" },
+        { "index": 2, "status": "ok", "id": results[0]["id"] },
+    ]));
+    let (_, count) = send(&app, Method::GET, "/todos/count?completed=true", None).await;
+    assert_eq!(count["count"], 2);
+}
+
+#[tokio::test]
+async fn batch_delete_removes_listed_todos_and_renumbers_the_rest() {
+    let app = test_app();
+
+    let (_, first) = send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "" }))).await;
+    let (_, second) = send(&app, Method::POST, "/todos", Some(json!({ "title": "second", "content": "" }))).await;
+    let (_, third) = send(&app, Method::POST, "/todos", Some(json!({ "title": "third", "content": "" }))).await;
+
+    let ids = json!({ "ids": [first["id"], second["id"], 999_999] });
+    let (status, body) = send(&app, Method::POST, "/todos/delete", Some(ids)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "deleted": 2 }));
+
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 1);
+    assert_eq!(todos[0]["id"], third["id"]);
+    assert_eq!(todos[0]["position"], 0);
+
+    // deleting them again finds nothing left to delete
+    let (_, body) = send(&app, Method::POST, "/todos/delete", Some(json!({ "ids": [first["id"]] }))).await;
+    assert_eq!(body, json!({ "deleted": 0 }));
+
+    let (status, _) = send(&app, Method::POST, "/todos/delete", Some(json!({ "ids": [] }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn search_ranks_matches_and_rejects_bad_queries() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "Call the bank", "content": "about the milk money" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "Buy milk", "content": "and bread" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "Walk the dog", "content": "" }))).await;
+
+    let (status, results) = send(&app, Method::GET, "/todos/search?q=milk", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let results = results.as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["title"], "Buy milk"); // a title match outranks a content match
+    assert!(results[0]["rank"].as_f64().unwrap() > results[1]["rank"].as_f64().unwrap());
+    assert_eq!(results[1]["highlight"], "about the <b>milk</b> money");
+
+    let (_, results) = send(&app, Method::GET, "/todos/search?q=milk%20%26%20bread", None).await;
+    assert_eq!(results.as_array().unwrap().len(), 1);
+
+    let (status, _) = send(&app, Method::GET, "/todos/search?q=milk%20%26", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn an_interrupted_export_can_be_resumed_with_a_range() {
+    let app = test_app();
+    for title in ["first", "second", "third"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "some content" }))).await;
+    }
+
+    let full = app.clone().oneshot(Request::builder().uri("/todos/export").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(full.status(), StatusCode::OK);
+    assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+    let etag = full.headers()[header::ETAG].to_str().unwrap().to_string();
+    let full = to_bytes(full.into_body(), usize::MAX).await.unwrap();
+
+    // the download broke off after 100 bytes, fetch the rest of the same export
+    let request = Request::builder()
+        .uri("/todos/export")
+        .header(header::RANGE, "bytes=100-")
+        .header(header::IF_RANGE, &etag)
+        .body(Body::empty())
+        .unwrap();
+    let rest = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(rest.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(rest.headers()[header::CONTENT_RANGE], format!("bytes 100-{}/{}", full.len() - 1, full.len()).as_str());
+    let rest = to_bytes(rest.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&rest[..], &full[100..]);
+
+    // once the todos changed the bytes the client has are stale, so it gets the whole new export instead
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "fourth", "content": "" }))).await;
+    let request = Request::builder()
+        .uri("/todos/export")
+        .header(header::RANGE, "bytes=100-")
+        .header(header::IF_RANGE, &etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder().uri("/todos/export").header(header::RANGE, "bytes=999999-").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+}
+
+#[tokio::test]
+async fn the_csv_list_can_be_resumed_with_a_range_but_not_the_ndjson_stream() {
+    let app = test_app();
+    for title in ["first", "second", "third"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "some content" }))).await;
+    }
+    let csv = |range: Option<&str>, if_range: Option<&str>| {
+        let mut request = Request::builder().uri("/todos").header(header::ACCEPT, "text/csv");
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        if let Some(if_range) = if_range {
+            request = request.header(header::IF_RANGE, if_range);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let full = csv(None, None).await.unwrap();
+    assert_eq!(full.status(), StatusCode::OK);
+    assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(full.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"todos.csv\"");
+    let etag = full.headers()[header::ETAG].to_str().unwrap().to_string();
+    let full = to_bytes(full.into_body(), usize::MAX).await.unwrap();
+
+    // the same list gives the same ETag, so the rest of the file can be fetched
+    let rest = csv(Some("bytes=20-"), Some(&etag)).await.unwrap();
+    assert_eq!(rest.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(rest.headers()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    let rest = to_bytes(rest.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&rest[..], &full[20..]);
+
+    // and once the list changed, the whole new file comes back
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "fourth", "content": "" }))).await;
+    let response = csv(Some("bytes=20-"), Some(&etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder().uri("/todos?format=ndjson").header(header::RANGE, "bytes=20-").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "none");
+}
+
+#[tokio::test]
+async fn export_then_import_round_trips() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "a" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "second", "content": "b", "due_date": "2030-01-01T00:00:00Z" }))).await;
+
+    let (status, export) = send(&app, Method::GET, "/todos/export", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(export["todos"].as_array().unwrap().len(), 2);
+
+    // merge: bad records are reported, the rest are imported after the existing todos
+    let mut document = export.clone();
+    document["todos"].as_array_mut().unwrap().push(json!({ "content": "no title" }));
+    let (status, report) = send(&app, Method::POST, "/todos/import", Some(document.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["failed"][0]["index"], 2);
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 4);
+
+    // replace: any bad record aborts the whole import
+    let (status, _) = send(&app, Method::POST, "/todos/import?mode=replace", Some(document)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 4);
+
+    // replace with the original export restores exactly that list
+    let (status, report) = send(&app, Method::POST, "/todos/import?mode=replace", Some(export.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["imported"], 2);
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let todos = todos.as_array().unwrap();
+    assert_eq!(todos.len(), 2);
+    for (restored, original) in todos.iter().zip(export["todos"].as_array().unwrap()) {
+        for field in ["title", "content", "completed", "created_at", "due_date", "position"] {
+            assert_eq!(restored[field], original[field], "{} differs", field);
+        }
+    }
+}
+
+#[tokio::test]
+async fn list_is_not_modified_since_its_last_modified_time() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "" }))).await;
+
+    let (status, headers, _) = send_with_headers(&app, Method::GET, "/todos", &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+    let last_modified = headers["last-modified"].to_str().unwrap().to_string();
+
+    // nothing changed since: 304 with no body
+    let (status, _, body) = send_with_headers(&app, Method::GET, "/todos", &[("if-modified-since", &last_modified)], None).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_null());
+
+    // the client's copy is a second older than the list: the full list again
+    let earlier = chrono::DateTime::parse_from_rfc2822(&last_modified).unwrap() - chrono::Duration::seconds(1);
+    let earlier = earlier.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let (status, _, body) = send_with_headers(&app, Method::GET, "/todos", &[("if-modified-since", &earlier)], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn a_todo_leaving_a_filtered_list_changes_its_last_modified() {
+    let state = test_state();
+    let app = todo_rs::app(state.clone());
+    for title in ["leaves", "stays"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+    }
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let leaves = format!("/todos/{}", todos[0]["id"]);
+
+    // the test's transaction has a single NOW(), so every todo is pretended to have last changed an hour before it
+    // (with the trigger that would set updated_at back to NOW() turned off for the moment)
+    let backdate = || {
+        let mut conn = state.pool.get().unwrap();
+        conn.batch_execute(
+            "ALTER TABLE todos DISABLE TRIGGER todos_set_updated_at; \
+             UPDATE todos SET updated_at = NOW() - INTERVAL '1 hour'; \
+             ALTER TABLE todos ENABLE TRIGGER todos_set_updated_at;",
+        )
+        .unwrap();
+    };
+    let last_modified = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let (status, headers, _) = send_with_headers(&app, Method::GET, uri, &[], None).await;
+            assert_eq!(status, StatusCode::OK);
+            headers["last-modified"].to_str().unwrap().to_string()
+        }
+    };
+
+    // completing "leaves" takes it out of ?completed=false, which is a change to that list even though "stays" didn't change
+    backdate();
+    let since = last_modified("/todos?completed=false").await;
+    send(&app, Method::POST, "/todos/complete", Some(json!({ "ids": [todos[0]["id"]] }))).await;
+    let (status, _, body) = send_with_headers(&app, Method::GET, "/todos?completed=false", &[("if-modified-since", &since)], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    // and archiving it takes it out of the default list
+    backdate();
+    let since = last_modified("/todos").await;
+    send(&app, Method::POST, &format!("{}/archive", leaves), None).await;
+    let (status, _, body) = send_with_headers(&app, Method::GET, "/todos", &[("if-modified-since", &since)], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["title"], "stays");
+}
+
+#[tokio::test]
+async fn fields_selects_a_subset_of_each_todo() {
+    let app = test_app();
+
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "long content" }))).await;
+
+    let (status, todos) = send(&app, Method::GET, "/todos?fields=id,title", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let todo = todos[0].as_object().unwrap();
+    assert_eq!(todo.keys().collect::<Vec<_>>(), ["id", "title"]);
+
+    // without ?fields= every field is there, and they're exactly the ones ?fields= accepts
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    let mut keys: Vec<_> = todos[0].as_object().unwrap().keys().cloned().collect();
+    let mut expected: Vec<_> = todo_rs::models::TODO_FIELDS.iter().map(|field| field.to_string()).collect();
+    keys.sort();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    let (status, _) = send(&app, Method::GET, "/todos?fields=id,titel", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn archived_todos_are_excluded_by_default() {
+    let app = test_app();
+
+    let (_, archived) = send(&app, Method::POST, "/todos", Some(json!({ "title": "old", "content": "" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "current", "content": "" }))).await;
+    let uri = format!("/todos/{}", archived["id"]);
+
+    let (status, todo) = send(&app, Method::POST, &format!("{}/archive", uri), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["archived"], true);
+
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 1);
+    assert_eq!(todos[0]["title"], "current");
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 1);
+
+    let (_, todos) = send(&app, Method::GET, "/todos?archived=true", None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 1);
+    assert_eq!(todos[0]["title"], "old");
+
+    // still editable while archived
+    let (status, todo) = send(&app, Method::POST, &uri, Some(json!({ "title": "older", "content": "" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(todo["archived"], true);
+
+    send(&app, Method::POST, &format!("{}/unarchive", uri), None).await;
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(todos.as_array().unwrap().len(), 2);
+
+    let (status, _) = send(&app, Method::POST, "/todos/999999/archive", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn attachments_are_added_embedded_and_removed() {
+    let app = test_app();
+
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Read paper", "content": "" }))).await;
+    let uri = format!("/todos/{}", todo["id"]);
+
+    let link = json!({ "url": "https://example.com/paper.pdf", "label": "the paper" });
+    let (status, attachment) = send(&app, Method::POST, &format!("{}/attachments", uri), Some(link)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(attachment["label"], "the paper");
+
+    for bad in ["example.com/paper.pdf", "ftp://example.com/paper.pdf", "javascript:alert(1)", "https://"] {
+        let (status, _) = send(&app, Method::POST, &format!("{}/attachments", uri), Some(json!({ "url": bad }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{} was accepted", bad);
+    }
+    let (status, _) = send(&app, Method::POST, "/todos/999999/attachments", Some(json!({ "url": "https://example.com" }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // only embedded when asked for
+    let (_, fetched) = send(&app, Method::GET, &uri, None).await;
+    assert!(fetched.get("attachments").is_none());
+    let (_, fetched) = send(&app, Method::GET, &format!("{}?include=attachments", uri), None).await;
+    assert_eq!(fetched["attachments"][0]["url"], "https://example.com/paper.pdf");
+    let (status, _) = send(&app, Method::GET, &format!("{}?include=comments", uri), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let attachment_uri = format!("{}/attachments/{}", uri, attachment["id"]);
+    let (status, _) = send(&app, Method::DELETE, &attachment_uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, Method::DELETE, &attachment_uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn deleting_a_todo_deletes_its_attachments() {
+    let state = test_state();
+    let app = todo_rs::app(state.clone());
+
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Read paper", "content": "" }))).await;
+    let uri = format!("/todos/{}", todo["id"]);
+    let (_, attachment) = send(&app, Method::POST, &format!("{}/attachments", uri), Some(json!({ "url": "https://example.com" }))).await;
+    send(&app, Method::DELETE, &uri, None).await;
+
+    // the todo is only soft-deleted, but its attachments are out of reach with it
+    let (status, _) = send(&app, Method::DELETE, &format!("{}/attachments/{}", uri, attachment["id"]), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // and go for good once the row itself does
+    let mut conn = state.pool.get().unwrap();
+    diesel::delete(todo_rs::schema::todos::table).execute(&mut conn).unwrap();
+    let left = todo_rs::schema::attachments::table.count().get_result::<i64>(&mut conn).unwrap();
+    assert_eq!(left, 0);
+}
+
+#[tokio::test]
+async fn get_todo_is_not_modified_while_the_etag_matches() {
+    let app = test_app();
+
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "first", "content": "" }))).await;
+    let uri = format!("/todos/{}", todo["id"]);
+
+    let (status, headers, _) = send_with_headers(&app, Method::GET, &uri, &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["cache-control"], "private, max-age=0, must-revalidate");
+    let etag = headers["etag"].to_str().unwrap().to_string();
+
+    let (status, headers, body) = send_with_headers(&app, Method::GET, &uri, &[("if-none-match", &etag)], None).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers["etag"], etag.as_str());
+    assert!(body.is_null());
+
+    // once the todo changes, the old ETag no longer matches
+    send(&app, Method::POST, &uri, Some(json!({ "title": "changed", "content": "" }))).await;
+    let (status, _, body) = send_with_headers(&app, Method::GET, &uri, &[("if-none-match", &etag)], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["title"], "changed");
+
+    // adding an attachment changes the ETag of the response that includes them
+    let with_attachments = format!("{}?include=attachments", uri);
+    let (_, headers, _) = send_with_headers(&app, Method::GET, &with_attachments, &[], None).await;
+    let etag = headers["etag"].to_str().unwrap().to_string();
+    send(&app, Method::POST, &format!("{}/attachments", uri), Some(json!({ "url": "https://example.com" }))).await;
+    let (status, _, _) = send_with_headers(&app, Method::GET, &with_attachments, &[("if-none-match", &etag)], None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_todo_whose_position_shifted_is_not_304() {
+    let app = test_app();
+    let mut uris = Vec::new();
+    for title in ["a", "b", "c"] {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+        uris.push(format!("/todos/{}", todo["id"]));
+    }
+    let mut etags = Vec::new();
+    for uri in &uris {
+        let (_, headers, _) = send_with_headers(&app, Method::GET, uri, &[], None).await;
+        etags.push(headers["etag"].to_str().unwrap().to_string());
+    }
+
+    // moving "c" to the top shifts "a" and "b" down, so none of the three cached copies is current anymore
+    send(&app, Method::POST, &format!("{}/move", uris[2]), Some(json!({ "position": 0 }))).await;
+    for ((uri, etag), position) in uris.iter().zip(&etags).zip([1, 2, 0]) {
+        let (status, _, body) = send_with_headers(&app, Method::GET, uri, &[("if-none-match", etag)], None).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(body["position"], position, "{}", uri);
+        assert_eq!(body["version"], 1, "{}", uri); // the ETag changed with the position, not the version
+    }
+
+    // a save based on the copy from before the move is stale too
+    let (status, _, _) = send_with_headers(&app, Method::POST, &uris[0], &[("if-match", &etags[0])], Some(json!({ "title": "a", "content": "x" }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+    // and deleting the top one shifts the rest up
+    let (_, headers, _) = send_with_headers(&app, Method::GET, &uris[0], &[], None).await;
+    let etag = headers["etag"].to_str().unwrap().to_string();
+    send(&app, Method::DELETE, &uris[2], None).await;
+    let (status, _, body) = send_with_headers(&app, Method::GET, &uris[0], &[("if-none-match", &etag)], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["position"], 0);
+}
+
+#[tokio::test]
+async fn changes_since_a_sync_include_updates_and_deletions() {
+    let app = test_app();
+
+    let (_, kept) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Kept", "content": "" }))).await;
+    let (_, gone) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Gone", "content": "" }))).await;
+
+    // a first sync, without since, gets everything
+    let (status, changes) = send(&app, Method::GET, "/todos/changes", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(changes["todos"].as_array().unwrap().len(), 2);
+    assert_eq!(changes["deleted"], json!([]));
+    assert_eq!(changes["next"], json!(null));
+
+    // the test transaction never commits, so NOW() stays the same for every write in it; a since before that sees them all
+    let since = "2000-01-01T00:00:00Z";
+    send(&app, Method::POST, &format!("/todos/{}", kept["id"]), Some(json!({ "title": "Kept", "content": "edited" }))).await;
+    send(&app, Method::DELETE, &format!("/todos/{}", gone["id"]), None).await;
+
+    let (_, changes) = send(&app, Method::GET, &format!("/todos/changes?since={}", since), None).await;
+    let todos = changes["todos"].as_array().unwrap();
+    assert_eq!(todos.len(), 1);
+    assert_eq!(todos[0]["content"], "edited");
+    assert_eq!(changes["deleted"], json!([gone["id"]]));
+    assert!(changes["server_time"].is_string());
+
+    // a since after every change has nothing to report
+    let (_, changes) = send(&app, Method::GET, "/todos/changes?since=2999-01-01T00:00:00Z", None).await;
+    assert_eq!(changes["todos"], json!([]));
+    assert_eq!(changes["deleted"], json!([]));
+
+    // and the deleted todo is gone from the rest of the API
+    let (status, _) = send(&app, Method::GET, &format!("/todos/{}", gone["id"]), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, listed) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn pages_of_same_timestamp_todos_have_no_duplicates_or_gaps() {
+    let app = test_app();
+
+    // every todo created in the test transaction gets the same created_at (NOW() is the transaction's start)
+    let mut created = Vec::new();
+    for n in 0..7 {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": format!("todo {}", n), "content": "" }))).await;
+        created.push(todo["id"].as_i64().unwrap());
+    }
+
+    for order in ["asc", "desc"] {
+        let mut seen = Vec::new();
+        for offset in (0..8).step_by(3) {
+            let uri = format!("/todos?sort=created_at&order={}&limit=3&offset={}", order, offset);
+            let (status, page) = send(&app, Method::GET, &uri, None).await;
+            assert_eq!(status, StatusCode::OK);
+            seen.extend(page.as_array().unwrap().iter().map(|todo| todo["id"].as_i64().unwrap()));
+        }
+
+        // ties are broken by id, in the same direction
+        let mut expected = created.clone();
+        if order == "desc" {
+            expected.reverse();
+        }
+        assert_eq!(seen, expected);
+    }
+
+    let (status, _) = send(&app, Method::GET, "/todos?limit=0", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, Method::GET, "/todos?sort=colour", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn the_deployment_default_sort_applies_when_the_request_has_none() {
+    let app = todo_rs::app(test_state_with(|config| {
+        config.default_sort = TodoSort::CreatedAt;
+        config.default_order = SortOrder::Desc;
+    }));
+    let mut created = Vec::new();
+    for n in 0..3 {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": format!("todo {}", n), "content": "" }))).await;
+        created.push(todo["id"].clone());
+    }
+    let ids = |todos: serde_json::Value| todos.as_array().unwrap().iter().map(|todo| todo["id"].clone()).collect::<Vec<_>>();
+
+    // newest first (they share a created_at here, so it comes down to the id)
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    assert_eq!(ids(todos), created.iter().rev().cloned().collect::<Vec<_>>());
+
+    // what the request asks for still wins, and the default fills in only what it leaves out
+    let (_, todos) = send(&app, Method::GET, "/todos?sort=position&order=asc", None).await;
+    assert_eq!(ids(todos), created);
+    let (_, todos) = send(&app, Method::GET, "/todos?sort=position", None).await;
+    assert_eq!(ids(todos), created.iter().rev().cloned().collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn creating_past_the_todo_limit_is_409() {
+    let app = todo_rs::app(test_state_with(|config| config.max_todos_per_user = Some(2)));
+
+    for title in ["a", "b"] {
+        let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let (status, body) = send(&app, Method::POST, "/todos", Some(json!({ "title": "c", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "todo limit reached");
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 2);
+
+    // deleted todos don't count, so removing one makes room again
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    send(&app, Method::DELETE, &format!("/todos/{}", todos[0]["id"]), None).await;
+    let (status, _) = send(&app, Method::POST, "/todos", Some(json!({ "title": "c", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn every_way_of_adding_todos_respects_the_limit() {
+    let app = todo_rs::app(test_state_with(|config| config.max_todos_per_user = Some(2)));
+    let (_, first) = send(&app, Method::PUT, "/todos/external/ext-1", Some(json!({ "title": "synced", "content": "" }))).await;
+    send(&app, Method::POST, "/todos", Some(json!({ "title": "second", "content": "" }))).await;
+    let record = json!({ "title": "imported", "content": "" });
+
+    let (status, _) = send(&app, Method::POST, &format!("/todos/{}/duplicate", first["id"]), None).await;
+    assert_eq!(status, StatusCode::CONFLICT, "duplicate");
+    let (status, _) = send(&app, Method::PUT, "/todos/external/ext-2", Some(json!({ "title": "new", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT, "first sync");
+    let (status, _) = send(&app, Method::POST, "/todos/import", Some(json!({ "todos": [record] }))).await;
+    assert_eq!(status, StatusCode::CONFLICT, "import");
+    let (status, _) = send(&app, Method::POST, "/todos/import?partial=true", Some(json!({ "todos": [record] }))).await;
+    assert_eq!(status, StatusCode::CONFLICT, "partial import");
+    let response = app.clone().oneshot(file_upload(&record.to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT, "import-file");
+
+    // a replace that doesn't fit changes nothing, not even the delete it starts with
+    let (status, _) = send(&app, Method::POST, "/todos/import?mode=replace", Some(json!({ "todos": [record, record, record] }))).await;
+    assert_eq!(status, StatusCode::CONFLICT, "replace");
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 2);
+
+    // what doesn't add a todo still works in a full list
+    let (status, _) = send(&app, Method::PUT, "/todos/external/ext-1", Some(json!({ "title": "resynced", "content": "" }))).await;
+    assert_eq!(status, StatusCode::OK, "a later sync");
+    let (status, _) = send(&app, Method::POST, "/todos/import?mode=replace", Some(json!({ "todos": [record, record] }))).await;
+    assert_eq!(status, StatusCode::OK, "a replace that fits");
+}
+
+#[tokio::test]
+async fn several_todos_can_be_fetched_by_id_in_one_call() {
+    // a page smaller than the lookup, which still gets every todo it asks for
+    let app = todo_rs::app(test_state_with(|config| {
+        config.default_page_size = 1;
+        config.max_page_size = 3;
+    }));
+    let mut created = Vec::new();
+    for title in ["a", "b", "c"] {
+        let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+        created.push(todo["id"].clone());
+    }
+
+    // in the list's order whatever the order asked for, and an id that doesn't exist is just left out
+    let uri = format!("/todos?ids={},{},999999", created[2], created[0]);
+    let (status, todos) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let titles = todos.as_array().unwrap().iter().map(|todo| todo["title"].clone()).collect::<Vec<_>>();
+    assert_eq!(titles, ["a", "c"]);
+
+    // it combines with the other filters and query params like any of them
+    send(&app, Method::POST, "/todos/complete", Some(json!({ "ids": [created[0]] }))).await;
+    let (_, todos) = send(&app, Method::GET, &format!("{}&completed=false&fields=id", uri), None).await;
+    assert_eq!(todos, json!([{ "id": created[2] }]));
+
+    // archived todos are found by id too, even though the plain list leaves them out
+    send(&app, Method::POST, &format!("/todos/{}/archive", created[1]), None).await;
+    let (_, todos) = send(&app, Method::GET, &format!("/todos?ids={},{}&fields=id", created[1], created[2]), None).await;
+    assert_eq!(todos, json!([{ "id": created[1] }, { "id": created[2] }]));
+
+    // ids that don't parse, an empty list and more ids than MAX_PAGE_SIZE are all 400
+    for uri in ["/todos?ids=1,two", "/todos?ids=", "/todos?ids=,", "/todos?ids=1,2,3,4"] {
+        let (status, _) = send(&app, Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "GET {}", uri);
+    }
+
+    // and deleting by id is POST /todos/delete's job, not a filter of DELETE /todos
+    let (status, _) = send(&app, Method::DELETE, &format!("/todos?ids={}", created[2]), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 2);
+}
+
+#[tokio::test]
+async fn envelope_wraps_a_page_with_its_total() {
+    let app = test_app();
+
+    for title in ["a", "b", "c"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+    }
+
+    let (status, body) = send(&app, Method::GET, "/todos?envelope=true&limit=2&offset=1&fields=title", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "data": [{ "title": "b" }, { "title": "c" }], "page": { "limit": 2, "offset": 1, "total": 3 } }));
+
+    // still a bare array without it
+    let (_, body) = send(&app, Method::GET, "/todos?limit=2", None).await;
+    assert_eq!(body.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn bad_json_bodies_are_rejected_with_detail() {
+    let app = test_app();
+
+    let (status, body) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Milk", "content": 5 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "invalid JSON body");
+    assert_eq!(body["field"], "content");
+    assert_eq!(body["detail"], "invalid type: integer `5`, expected a string");
+
+    let (status, body) = send(&app, Method::POST, "/todos", Some(json!({ "content": "" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], json!(null));
+    assert_eq!(body["detail"], "missing field `title`");
+
+    // malformed JSON, and a body that doesn't say it's JSON
+    for (content_type, expected) in [("application/json", StatusCode::UNPROCESSABLE_ENTITY), ("text/plain", StatusCode::UNSUPPORTED_MEDIA_TYPE)] {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/todos")
+            .header("content-type", content_type)
+            .body(Body::from(r#"{"title": "Milk","#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), expected, "for {}", content_type);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["error"], "invalid JSON body");
+    }
+}
+
+#[tokio::test]
+async fn writes_with_a_non_json_body_get_415_before_the_handler_runs() {
+    let app = test_app();
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Milk", "content": "" }))).await;
+
+    let write = |method: Method, uri: String, content_type: Option<&'static str>, body: &'static str| {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        app.clone().oneshot(request.body(Body::from(body)).unwrap())
+    };
+
+    // archive takes no body, so only the guard can be what turns this one away (the handler would archive the todo)
+    let response = write(Method::POST, format!("/todos/{}/archive", todo["id"]), Some("text/plain"), "please").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let response = write(Method::PUT, "/todos/external/abc".to_string(), None, r#"{"title": "x", "content": ""}"#).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(body["detail"], "expected a Content-Type of application/json");
+
+    // the upload route is let through by its multipart Content-Type, not its path: anything else sent there is turned away,
+    // and an upload sent to a JSON route still gets the extractor's 415
+    let response = write(Method::POST, "/todos/import-file".to_string(), Some("text/plain"), "Milk").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let upload = json!({ "title": "Bread", "content": "" }).to_string();
+    let response = app.clone().oneshot(file_upload(&upload)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = write(Method::POST, "/todos".to_string(), Some("multipart/form-data; boundary=XYZ"), "--XYZ--\r\n").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // a charset parameter is fine, and so is a write without a body
+    let response = write(Method::POST, format!("/todos/{}/move", todo["id"]), Some("application/json; charset=utf-8"), r#"{"position": 0}"#)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = write(Method::POST, format!("/todos/{}/archive", todo["id"]), None, "").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn pretty_json_is_indented_only_when_asked_for() {
+    let app = test_app();
+    let (_, todo) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Milk", "content": "" }))).await;
+
+    let fetch = |uri: String, pretty_header: bool| {
+        let mut request = Request::builder().uri(uri).header(header::ACCEPT, "application/json");
+        if pretty_header {
+            request = request.header("x-pretty", "true");
+        }
+        let response = app.clone().oneshot(request.body(Body::empty()).unwrap());
+        async move {
+            let response = response.await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+        }
+    };
+
+    let compact = fetch(format!("/todos/{}", todo["id"]), false).await;
+    assert!(!compact.contains('\n'));
+
+    for pretty in [fetch(format!("/todos/{}?pretty=true", todo["id"]), false).await, fetch(format!("/todos/{}", todo["id"]), true).await] {
+        assert!(pretty.contains("\n  \"title\": \"Milk\""), "{}", pretty);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&pretty).unwrap(), todo); // the same JSON, only laid out differently
+    }
+
+    let (status, _) = send(&app, Method::GET, "/todos?pretty=maybe", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn logging_bodies_leaves_them_intact() {
+    let app = todo_rs::app(test_state_with(|config| {
+        config.log_bodies = true;
+        config.log_body_max_bytes = 8; // shorter than the bodies, which must still arrive whole
+    }));
+
+    let (status, created) = send(&app, Method::POST, "/todos", Some(json!({ "title": "Buy milk", "content": "2 litres" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["content"], "2 litres");
+
+    let (status, fetched) = send(&app, Method::GET, &format!("/todos/{}", created["id"]), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched, created);
+}
+
+#[tokio::test]
+async fn board_splits_the_list_into_limited_columns() {
+    let app = test_app();
+
+    for title in ["a", "b", "c", "d", "e"] {
+        send(&app, Method::POST, "/todos", Some(json!({ "title": title, "content": "" }))).await;
+    }
+    let (_, todos) = send(&app, Method::GET, "/todos", None).await;
+    for todo in &todos.as_array().unwrap()[1..4] {
+        send(&app, Method::POST, &format!("/todos/{}", todo["id"]), Some(json!({ "title": todo["title"], "content": "", "completed": true }))).await;
+    }
+
+    let titles = |column: &serde_json::Value| column.as_array().unwrap().iter().map(|todo| todo["title"].clone()).collect::<Vec<_>>();
+
+    let (status, board) = send(&app, Method::GET, "/todos/board", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(titles(&board["pending"]), ["a", "e"]);
+    assert_eq!(titles(&board["completed"]), ["b", "c", "d"]);
+
+    let (_, board) = send(&app, Method::GET, "/todos/board?limit=2", None).await;
+    assert_eq!(titles(&board["pending"]), ["a", "e"]);
+    assert_eq!(titles(&board["completed"]), ["b", "c"]);
+}
+
+#[tokio::test]
+async fn upserting_by_external_id_creates_once_then_updates() {
+    let app = test_app();
+
+    let (status, created) = send(&app, Method::PUT, "/todos/external/jira-42", Some(json!({ "title": "Fix login", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["external_id"], "jira-42");
+
+    let (status, updated) = send(&app, Method::PUT, "/todos/external/jira-42", Some(json!({ "title": "Fix login page", "content": "on mobile" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["id"], created["id"]);
+    assert_eq!(updated["title"], "Fix login page");
+    assert_eq!(updated["content"], "on mobile");
+    assert_eq!(updated["position"], created["position"]);
+
+    let (_, count) = send(&app, Method::GET, "/todos/count", None).await;
+    assert_eq!(count["count"], 1);
+
+    // once deleted here, the next sync brings it back as a new todo
+    send(&app, Method::DELETE, &format!("/todos/{}", created["id"]), None).await;
+    let (status, recreated) = send(&app, Method::PUT, "/todos/external/jira-42", Some(json!({ "title": "Fix login", "content": "" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_ne!(recreated["id"], created["id"]);
+}
+
+// a multipart/form-data request uploading `contents` as the "file" field
+fn file_upload(contents: &str) -> Request<Body> {
+    let body = format!(
+        "--XYZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"todos.ndjson\"\r\nContent-Type: application/x-ndjson\r\n\r\n{}\r\n--XYZ--\r\n",
+        contents
+    );
+    Request::builder()
+        .method(Method::POST)
+        .uri("/todos/import-file")
+        .header("content-type", "multipart/form-data; boundary=XYZ")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn import_file_inserts_good_lines_and_reports_bad_ones() {
+    let app = test_app();
+
+    // more than one batch of todos, with a broken line and a blank one in between
+    let mut lines: Vec<String> = (0..1200).map(|n| json!({ "title": format!("todo {}", n), "content": "" }).to_string()).collect();
+    lines.insert(2, r#"{"title": "no content"}"#.to_string());
+    lines.insert(4, String::new());
+    let response = app.clone().oneshot(file_upload(&lines.join("\n"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(report["imported"], 1200);
+    assert_eq!(report["failed_total"], 1);
+    assert_eq!(report["failed"][0]["line"], 3);
+
+    let (_, body) = send(&app, Method::GET, "/todos?limit=3&offset=1199", None).await;
+    let titles: Vec<&str> = body.as_array().unwrap().iter().map(|todo| todo["title"].as_str().unwrap()).collect();
+    assert_eq!(titles, vec!["todo 1199"]); // in file order, at the end of the list
+}
+
+#[tokio::test]
+async fn import_file_rejects_files_over_the_limit() {
+    let app = todo_rs::app(test_state_with(|config| config.import_file_max_bytes = 1024));
+
+    let line = json!({ "title": "padding", "content": "x".repeat(100) }).to_string();
+    let response = app.oneshot(file_upload(&vec![line; 20].join("\n"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}